@@ -0,0 +1,7 @@
+//! Scans the tickets of a help desk for a flag. The command-line interface is behind the default
+//! `cli` feature; without it, only the scanning itself is built.
+
+pub mod profile;
+pub mod scan;
+pub mod skipseq;
+pub mod transform;
@@ -1,14 +1,37 @@
-use clap::Parser;
-use reqwest::{Client, Url, redirect::Policy};
+use clap::{Parser, value_parser};
+use regex::Regex;
+use reqwest::{Client, StatusCode, Url, redirect::Policy};
+use scraper::Selector;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fmt::Write as _,
+    fs::write,
+    future::poll_fn,
+    io::{self, Write},
+    iter::from_fn,
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::Poll,
+    time::{Duration, Instant},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, stdin},
+    select,
+    signal::ctrl_c,
+    time::sleep,
+};
+use tracing::subscriber::set_global_default;
 
-#[allow(dead_code)]
-mod skipseq;
-
-use skipseq::SkipSeq;
-
-mod scan;
-
-use scan::*;
+use chal1::{
+    profile::ChromeTrace,
+    scan::*,
+    skipseq::{Descending, Sequence, SkipSeq, SparseSkipSeq},
+    transform::{Transform, apply_all},
+};
 
 #[derive(Parser)]
 struct Cli {
@@ -17,6 +40,102 @@ struct Cli {
     #[arg(short, long)]
     /// Prints information about progress.
     verbose: bool,
+    #[arg(long)]
+    /// Searches the raw ticket pages for the flag instead of parsing them.
+    raw: bool,
+    #[arg(short, long)]
+    /// Reads commands from stdin to scan individual tickets.
+    interactive: bool,
+    #[arg(long)]
+    /// Never searches the tickets of a user more than once.
+    only_new_users: bool,
+    #[arg(long)]
+    /// Prints where and when the flag was found, for use in a write-up.
+    report_flag: bool,
+    #[arg(long)]
+    /// Never requests user pages, only searching the individual ticket pages. Implies `--raw`.
+    no_login: bool,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    /// The expected number of tickets per user, used to reserve memory up front.
+    tickets_per_user: usize,
+    #[arg(long, value_name = "SELECTOR")]
+    /// Searches the elements matching a CSS selector within each ticket before its header and
+    /// description. Has no effect with `--raw`.
+    flag_selector: Option<Box<str>>,
+    #[arg(long, value_name = "K")]
+    /// Gives up once K tickets in a row belong to users that were already searched, assuming that
+    /// every user has been found.
+    stop_after_stable_users: Option<usize>,
+    #[arg(long, value_name = "TRANSFORMS", value_delimiter = ',')]
+    /// Transformations to apply to the flag in order before printing it, e.g. `trim,base64`.
+    flag_transform: Vec<Transform>,
+    #[arg(long)]
+    /// Prints the source of a page that did not match the expected structure before exiting.
+    dump_on_parse_fail: bool,
+    #[arg(long, value_name = "N")]
+    /// Stops scanning after sending N requests.
+    max_requests: Option<usize>,
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    /// The pattern of the flag, `flag\{(.*?)\}` by default. Its first capture group, or the whole
+    /// match if it has none, is taken as the flag.
+    flag_pattern: Option<Regex>,
+    #[arg(long)]
+    /// Skips tickets on a user page that cannot be parsed, with a warning, instead of exiting.
+    skip_malformed_tickets: bool,
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    /// The pattern of a ticket id on user pages, `^\s*Ticket #(\d+)\s*$` by default. Its first
+    /// capture group, or the whole match if it has none, is parsed as the id after removing any
+    /// non-digits, e.g. `#(\S+)` for `#TKT-1,024`.
+    ticket_id_pattern: Option<Regex>,
+    #[arg(long, value_name = "PATH")]
+    /// Writes the ids of the tickets that were scanned without finding the flag to PATH, as
+    /// ranges such as `3-7`, one per line.
+    dump_scanned: Option<PathBuf>,
+    #[arg(long)]
+    /// Also searches the full markup of each ticket, including HTML comments. Has no effect with
+    /// `--raw`, which always does.
+    search_markup: bool,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    /// Resumes the scan up to N times after it fails due to a request error, starting with the
+    /// ticket that failed.
+    retry_scan: usize,
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    /// How long to wait before resuming the scan with `--retry-scan`.
+    retry_delay: u64,
+    #[arg(long)]
+    /// Keeps track of the eliminated tickets in a set rather than a bitmap, which uses less memory
+    /// when few tickets far apart are eliminated.
+    sparse_skips: bool,
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    /// Only accepts flags whose whole match, e.g. `flag{...}`, matches REGEX, reporting the others
+    /// as rejected candidates. Useful with a loose `--flag-pattern`.
+    validate: Option<Regex>,
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = value_parser!(u64).range(1..))]
+    /// Scans up to N tickets at once, sharing the eliminated tickets between them.
+    concurrency: u64,
+    #[arg(long, value_name = "PATH")]
+    /// Records how long fetching, parsing and matching take and writes the trace to PATH in the
+    /// Chrome trace event format, e.g. for Perfetto or flamegraph tools.
+    profile: Option<PathBuf>,
+    #[arg(long, value_name = "N")]
+    /// Stops scanning once N bytes of responses have been downloaded.
+    max_bytes: Option<u64>,
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value_t = 1,
+        conflicts_with = "prioritize_recent"
+    )]
+    /// The first ticket id to scan.
+    start_id: usize,
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = value_parser!(u64).range(1..), conflicts_with = "prioritize_recent")]
+    /// Only scans every Nth ticket id from `--start-id`, for when the ids are known to be spaced
+    /// out, e.g. N = 10 for multiples of 10.
+    step: u64,
+    #[arg(long)]
+    /// Finds the highest ticket id first and scans downwards from it, for when the flag is likely
+    /// in a recent ticket.
+    prioritize_recent: bool,
 }
 
 #[tokio::main]
@@ -24,37 +143,453 @@ async fn main() -> Result<(), ScanError> {
     let Cli {
         index_url,
         verbose,
+        raw,
+        interactive,
+        only_new_users,
+        report_flag,
+        no_login,
+        tickets_per_user,
+        flag_selector,
+        stop_after_stable_users,
+        flag_transform,
+        dump_on_parse_fail,
+        max_requests,
+        flag_pattern,
+        skip_malformed_tickets,
+        ticket_id_pattern,
+        dump_scanned,
+        search_markup,
+        retry_scan,
+        retry_delay,
+        sparse_skips,
+        validate,
+        concurrency,
+        profile,
+        max_bytes,
+        start_id,
+        step,
+        prioritize_recent,
     } = Cli::parse();
+    let trace = profile.as_ref().map(|_| {
+        let trace = Arc::new(ChromeTrace::default());
+        set_global_default(Arc::clone(&trace)).expect("Failed to start profiling.");
+        trace
+    });
+    let concurrency = concurrency as usize;
+    let step = step as usize;
+    let default = ScanConfig::default();
+    let config = ScanConfig {
+        flag_pattern: flag_pattern.unwrap_or(default.flag_pattern),
+        validate,
+        flag_selector: flag_selector
+            .map(|s| Selector::parse(&s).map_err(|e| e.to_string()))
+            .transpose()
+            .expect("Invalid flag selector."),
+        tickets_per_user,
+        ticket_id_pattern: ticket_id_pattern.unwrap_or(default.ticket_id_pattern),
+        skip_malformed: skip_malformed_tickets,
+        search_markup,
+    };
+    // Requesting a user page is what starts a session, so only the ticket pages can be searched.
+    let raw = raw || no_login;
     let client = Client::builder()
         .cookie_store(true)
         .redirect(Policy::limited(1))
         .build()
         .expect("Failed to initialize client.");
 
-    let mut checked_ids = SkipSeq::new(1);
-    // Scanning could be made parallel, but non-trivially and ideally with cancellation.
-    loop {
-        let next_id = checked_ids.next();
+    if interactive {
+        repl(&client, &index_url, raw, &config).await;
+        return Ok(());
+    }
+
+    let stats = Stats::with_max_requests(max_requests).with_max_bytes(max_bytes);
+    // The flag may be right on the index page, in which case no tickets need to be scanned. Failing
+    // to fetch it is no reason not to scan them, though.
+    match scan_index(&client, index_url.clone(), &config, &stats).await {
+        Ok(Some((status, found))) => {
+            print_flag(None, status, &found, report_flag, &flag_transform);
+            return Ok(());
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to search the index page: {e}"),
+    }
+
+    let checked_ids: Box<dyn Sequence> = if prioritize_recent {
+        let last = find_last_id(&client, &index_url, &stats).await?;
         if verbose {
-            println!("Fetching ticket {next_id}...");
+            println!("Scanning down from ticket {last}.");
+        }
+        if sparse_skips {
+            Box::new(Descending::new(SparseSkipSeq::new(0), last))
+        } else {
+            Box::new(Descending::new(
+                SkipSeq::with_capacity(0, tickets_per_user),
+                last,
+            ))
         }
+    } else if sparse_skips {
+        Box::new(SparseSkipSeq::new(start_id))
+    } else {
+        Box::new(SkipSeq::with_capacity(start_id, tickets_per_user))
+    };
+    // The workers run on this task and never hold a borrow across an await, so plain cells suffice.
+    let checked_ids = RefCell::new(checked_ids);
+    // The users whose tickets were fetched and searched without finding the flag.
+    let searched_users = RefCell::new(HashSet::new());
+    // The users that any ticket so far belonged to, whether they were searched or not.
+    let seen_users = RefCell::new(HashSet::new());
+    // The users whose tickets a worker is fetching, so that no other worker fetches them as well.
+    let searching_users = RefCell::new(HashSet::new());
+    // The number of consecutive tickets by users that were already searched.
+    let stable_users = Cell::new(0);
+    // The ticket that each worker is scanning, or scanned last.
+    let current_ids = RefCell::new(vec![None; concurrency]);
+    // The eliminated tickets, which are only kept track of when needed, since the set takes far
+    // more memory than the sequence.
+    let track_scanned = dump_scanned.is_some() || concurrency > 1;
+    let scanned = RefCell::new(HashSet::new());
+    let retries = Cell::new(0);
+    let start = Instant::now();
+    // Returns whether the search is over, i.e. the flag was found or given up on, rather than the
+    // ids having run out.
+    let worker = async |worker: usize| {
+        let mut retry_id = None;
+        loop {
+            let res = async {
+                loop {
+                    // A ticket that failed to be scanned is scanned again first.
+                    // Ids off the stride are passed over without being fetched.
+                    let next_id = retry_id.take().or_else(|| {
+                        from_fn(|| checked_ids.borrow_mut().next())
+                            .find(|id| (id - start_id).is_multiple_of(step))
+                    });
+                    let Some(next_id) = next_id else {
+                        return Ok(false);
+                    };
+                    current_ids.borrow_mut()[worker] = Some(next_id);
+                    if verbose {
+                        println!("Fetching ticket {next_id}...");
+                    }
+
+                    if raw {
+                        let res =
+                            scan_raw(&client, index_url.clone(), next_id, &config, &stats).await;
+                        // The status is the only sign of having passed the last ticket. Scanning
+                        // down, there is no last ticket to pass, only gaps.
+                        if let Err(ScanError::NotFound) = res {
+                            if prioritize_recent {
+                                continue;
+                            }
+                            return Ok(false);
+                        }
+                        if let Some((status, found)) = res? {
+                            print_flag(Some(next_id), status, &found, report_flag, &flag_transform);
+                            return Ok::<_, ScanError>(true);
+                        }
+                        if track_scanned {
+                            scanned.borrow_mut().insert(next_id);
+                        }
+                        continue;
+                    }
+
+                    let username =
+                        fetch_username(&client, index_url.clone(), next_id, &stats).await?;
+                    let new_user = seen_users.borrow_mut().insert(username.clone());
+                    let stable = if new_user { 0 } else { stable_users.get() + 1 };
+                    stable_users.set(stable);
+                    if stop_after_stable_users.is_some_and(|k| stable >= k) {
+                        eprintln!("No new users in the last {stable} tickets, giving up.");
+                        return Ok(true);
+                    }
+
+                    // A user only counts as searched once their page was, so that a failed search is
+                    // retried.
+                    let searched = searched_users.borrow().contains(&username);
+                    // Another worker may have eliminated the ticket while its author was fetched.
+                    if searched && scanned.borrow().contains(&next_id) {
+                        if verbose {
+                            println!("Ticket {next_id} was eliminated meanwhile.");
+                        }
+                        continue;
+                    }
 
-        match scan(&client, index_url.clone(), next_id).await? {
-            Scan::Success { flag, id } => {
-                println!("Found flag: {flag} (ticket #{id})");
-                return Ok(());
+                    // The tickets of a searched user should all be skipped already, but if the user page
+                    // was incomplete, searching it again is unlikely to help.
+                    if only_new_users && searched {
+                        if verbose {
+                            println!("User \"{username}\" was already searched.");
+                        }
+                        continue;
+                    }
+
+                    if !searching_users.borrow_mut().insert(username.clone()) {
+                        if verbose {
+                            println!("User \"{username}\" is being searched by another worker.");
+                        }
+                        continue;
+                    }
+                    let res = scan_user(
+                        &client,
+                        index_url.clone(),
+                        username.clone(),
+                        &config,
+                        &stats,
+                    )
+                    .await;
+                    searching_users.borrow_mut().remove(&username);
+                    match res? {
+                        Scan::Success { id, status, found } => {
+                            print_flag(Some(id), status, &found, report_flag, &flag_transform);
+                            return Ok(true);
+                        }
+                        Scan::Failure { username, ids } => {
+                            if verbose {
+                                println!(
+                                    "Searched user \"{username}\", eliminated {} tickets.",
+                                    ids.len()
+                                );
+                            }
+                            searched_users.borrow_mut().insert(username);
+                            checked_ids.borrow_mut().skip_all(&ids);
+                            if track_scanned {
+                                scanned.borrow_mut().extend(ids);
+                            }
+                        }
+                    }
+                }
             }
-            Scan::Failure { username, ids } => {
-                if verbose {
-                    println!(
-                        "Searched user \"{username}\", eliminated {} tickets.",
-                        ids.len()
+            .await;
+            match res {
+                // Other errors would most likely recur, e.g. past the last ticket.
+                Err(ScanError::Io(e)) if retries.get() < retry_scan => {
+                    retries.set(retries.get() + 1);
+                    eprintln!(
+                        "Scan failed: {e}. Retrying in {retry_delay} s ({}/{retry_scan})...",
+                        retries.get()
                     );
+                    retry_id = current_ids.borrow()[worker];
+                    sleep(Duration::from_secs(retry_delay)).await;
+                }
+                res => return res,
+            }
+        }
+    };
+    let search = async {
+        let over = first_to_stop((0..concurrency).map(worker).collect()).await?;
+        if !over {
+            eprintln!("Ran out of ticket ids.");
+        }
+        Ok(())
+    };
+
+    // An error ends the search, but what was scanned until then is still reported before it is
+    // returned.
+    let (interrupted, res) = select! {
+        res = search => {
+            if let Err(ScanError::UnexpectedStructure(page)) = &res
+                && dump_on_parse_fail
+            {
+                eprintln!("{page}");
+            }
+            let res = match res {
+                Err(ScanError::LimitReached) => {
+                    eprintln!("Stopped after {} requests.", stats.requests());
+                    Ok(())
+                }
+                Err(ScanError::BudgetReached) => {
+                    eprintln!("Stopped after downloading {} bytes.", stats.bytes());
+                    Ok(())
+                }
+                res => res,
+            };
+            (false, res)
+        }
+        _ = shutdown_signal() => (true, Ok(())),
+    };
+    if interrupted {
+        let current_ids = current_ids.into_inner();
+        let ids: Vec<_> = current_ids
+            .iter()
+            .flatten()
+            .map(|id| format!("#{id}"))
+            .collect();
+        match ids.len() {
+            0 => eprintln!("Interrupted before scanning any tickets."),
+            1 => eprintln!("Interrupted while scanning ticket {}.", ids[0]),
+            _ => eprintln!("Interrupted while scanning tickets {}.", ids.join(", ")),
+        }
+    }
+    if let (Some(path), Some(trace)) = (profile, trace) {
+        trace.write(&path).expect("Failed to write profile.");
+    }
+    if let Some(path) = dump_scanned {
+        let scanned = scanned.into_inner().into_iter().collect();
+        dump_ids(&path, scanned).expect("Failed to dump scanned ids.");
+    }
+    if verbose {
+        println!("{}", checked_ids.borrow());
+        println!("{}", stats.summary(start.elapsed()));
+    }
+    res
+}
+
+/// Runs the workers concurrently on the current task until one of them stops the search, cancelling
+/// the rest, or until all of them finish. A worker that fails only stops itself, since the others may
+/// still be scanning tickets before the one that failed; the first error is returned once all of
+/// them have finished. Returns whether the search was stopped.
+async fn first_to_stop<F>(workers: Vec<F>) -> Result<bool, ScanError>
+where
+    F: Future<Output = Result<bool, ScanError>>,
+{
+    let mut workers: Vec<_> = workers.into_iter().map(Box::pin).collect();
+    let mut error = None;
+    poll_fn(|cx| {
+        let mut i = 0;
+        while i < workers.len() {
+            match workers[i].as_mut().poll(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(Ok(true)) => return Poll::Ready(Ok(true)),
+                Poll::Ready(res) => {
+                    drop(workers.swap_remove(i));
+                    if let Err(e) = res {
+                        error.get_or_insert(e);
+                    }
                 }
-                for id in ids {
-                    _ = checked_ids.skip(id);
+            }
+        }
+        if workers.is_empty() {
+            Poll::Ready(error.take().map_or(Ok(false), Err))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Writes the ids to `path` as sorted, merged ranges, one per line, e.g. `3-7`.
+fn dump_ids(path: &Path, mut ids: Vec<usize>) -> io::Result<()> {
+    ids.sort_unstable();
+    ids.dedup();
+    let mut text = String::new();
+    let mut ids = ids.into_iter().peekable();
+    while let Some(first) = ids.next() {
+        let mut last = first;
+        while let Some(id) = ids.next_if_eq(&(last + 1)) {
+            last = id;
+        }
+        _ = if first == last {
+            writeln!(text, "{first}")
+        } else {
+            writeln!(text, "{first}-{last}")
+        };
+    }
+    write(path, text)
+}
+
+/// Prints a found flag, optionally over several lines describing where and when it was found. `id`
+/// is `None` if it was found on the index page rather than in a ticket.
+fn print_flag(
+    id: Option<usize>,
+    status: StatusCode,
+    found: &FlagMatch,
+    report: bool,
+    transforms: &[Transform],
+) {
+    let transformed = if transforms.is_empty() {
+        None
+    } else {
+        apply_all(transforms, &found.flag)
+            .inspect_err(|e| eprintln!("Failed to transform flag: {e}"))
+            .ok()
+    };
+
+    if !report {
+        let location = id.map_or("index page".into(), |id| format!("ticket #{id}"));
+        match transformed {
+            Some(transformed) => println!(
+                "Found flag: {transformed} ({location}, originally {})",
+                found.flag
+            ),
+            None => println!("Found flag: {} ({location})", found.flag),
+        }
+        return;
+    }
+
+    let time = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .expect("Failed to format time.");
+    println!("Flag:     {}", found.flag);
+    if let Some(transformed) = transformed {
+        println!("Decoded:  {transformed}");
+    }
+    if let Some(id) = id {
+        println!("Ticket:   #{id}");
+    }
+    println!("Field:    {}", found.field);
+    println!("Context:  {:?}", found.context);
+    println!("Status:   {status}");
+    println!("Found at: {time}");
+}
+
+/// Completes once the process is asked to stop, by Ctrl-C or, on Unix, `SIGTERM`.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install signal handler.");
+        select! {
+            _ = ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        _ = ctrl_c().await;
+    }
+}
+
+/// Reads commands from stdin until EOF or `quit`, reusing the session of `client`.
+async fn repl(client: &Client, index_url: &Url, raw: bool, config: &ScanConfig) {
+    let stats = Stats::default();
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        print!("> ");
+        _ = io::stdout().flush();
+        let Some(line) = lines.next_line().await.expect("Failed to read input.") else {
+            break;
+        };
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("scan"), Some(id), None) => {
+                let Ok(id) = id.parse() else {
+                    eprintln!("Invalid ticket id: {id}");
+                    continue;
+                };
+                if raw {
+                    match scan_raw(client, index_url.clone(), id, config, &stats).await {
+                        Ok(Some((_, found))) => {
+                            println!("Found flag: {} (ticket #{id})", found.flag)
+                        }
+                        Ok(None) => println!("No flag in ticket #{id}."),
+                        Err(e) => eprintln!("{e}"),
+                    }
+                } else {
+                    match scan(client, index_url.clone(), id, config, &stats).await {
+                        Ok(Scan::Success { id, found, .. }) => {
+                            println!("Found flag: {} (ticket #{id})", found.flag)
+                        }
+                        Ok(Scan::Failure { username, ids }) => {
+                            println!("User \"{username}\" has no flag in tickets {ids:?}.")
+                        }
+                        Err(e) => eprintln!("{e}"),
+                    }
                 }
             }
+            (Some("quit" | "exit"), None, None) => break,
+            (None, ..) => {}
+            _ => eprintln!("Commands: scan <id>, quit"),
         }
     }
 }
@@ -1,13 +1,107 @@
+use encoding_rs::{Encoding, UTF_8};
 use regex::Regex;
-use reqwest::{Client, Error as ReqwestError, Url};
-use scraper::{ElementRef, Html, Selector};
-use std::{num::ParseIntError, sync::OnceLock};
+use reqwest::{Client, Error as ReqwestError, Response, StatusCode, Url, header::CONTENT_TYPE};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::{
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    num::ParseIntError,
+    ops::Range,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 use thiserror::Error;
+use tracing::{Instrument, info_span};
+
+const CONTEXT_CHARS: usize = 20;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Scan {
-    Success { flag: Box<str>, id: usize },
-    Failure { username: Box<str>, ids: Vec<usize> },
+    Success {
+        id: usize,
+        status: StatusCode,
+        found: FlagMatch,
+    },
+    Failure {
+        username: Box<str>,
+        ids: Vec<usize>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Header,
+    Description,
+    /// The elements matching a user-supplied selector.
+    Selected,
+    /// The full markup of a ticket, including HTML comments.
+    Markup,
+    /// The whole ticket page, when searched without parsing.
+    Page,
+    /// The whole index page, which is searched before any tickets.
+    IndexPage,
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(match self {
+            Self::Header => "header",
+            Self::Description => "description",
+            Self::Selected => "selected elements",
+            Self::Markup => "markup",
+            Self::Page => "page",
+            Self::IndexPage => "index page",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlagMatch {
+    pub flag: Box<str>,
+    pub field: Field,
+    /// The flag along with up to `CONTEXT_CHARS` characters on either side.
+    pub context: Box<str>,
+    /// The byte range of the whole match, e.g. `flag{...}`, within the field.
+    pub range: Range<usize>,
+}
+
+impl FlagMatch {
+    /// Finds the first match of `pattern` whose whole text also matches `validate`, if given.
+    fn find(
+        pattern: &Regex,
+        validate: Option<&Regex>,
+        field: Field,
+        haystack: &str,
+    ) -> Option<Self> {
+        let captures = pattern.captures_iter(haystack).find(|c| {
+            let whole = c.get(0).unwrap().as_str();
+            let valid = validate.is_none_or(|v| v.is_match(whole));
+            if !valid {
+                eprintln!("Rejected flag candidate: {whole}");
+            }
+            valid
+        })?;
+        let whole = captures.get(0).unwrap();
+
+        let start = haystack[..whole.start()]
+            .char_indices()
+            .rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        let end = haystack[whole.end()..]
+            .char_indices()
+            .nth(CONTEXT_CHARS)
+            .map_or(haystack.len(), |(i, _)| whole.end() + i);
+
+        Some(Self {
+            flag: captures.get(1).unwrap_or(whole).as_str().into(),
+            field,
+            context: haystack[start..end].into(),
+            range: whole.range(),
+        })
+    }
 }
 
 #[derive(Debug, Error)]
@@ -20,6 +114,163 @@ pub enum ScanError {
     UnexpectedFormat,
     #[error("{0}")]
     TicketId(#[from] ParseIntError),
+    /// None of the elements the page is searched for exist, e.g. due to broken markup.
+    #[error("Page did not match expected structure.")]
+    UnexpectedStructure(RawPage),
+    /// The page responded with 404, e.g. past the last ticket.
+    #[error("Page not found.")]
+    NotFound,
+    #[error("Reached the maximum number of requests.")]
+    LimitReached,
+    #[error("Reached the maximum number of bytes to download.")]
+    BudgetReached,
+}
+
+/// The source of a page, which is only summarized when debug formatted.
+pub struct RawPage(pub Box<str>);
+
+impl Debug for RawPage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "RawPage({} bytes)", self.0.len())
+    }
+}
+
+impl Display for RawPage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.0)
+    }
+}
+
+/// Options affecting how tickets are searched.
+#[derive(Clone, Debug)]
+pub struct ScanConfig {
+    /// The pattern of the flag. Its first capture group, or the whole match if it has none, is
+    /// taken as the flag.
+    pub flag_pattern: Regex,
+    /// If given, only matches of the flag pattern whose whole text, e.g. `flag{...}`, also matches
+    /// this are taken as the flag.
+    pub validate: Option<Regex>,
+    /// If given, the elements matching this are searched before the header and description of
+    /// each ticket.
+    pub flag_selector: Option<Selector>,
+    /// The expected number of tickets per user, used to reserve memory up front.
+    pub tickets_per_user: usize,
+    /// The pattern of the text of a ticket id element. Its first capture group, or the whole match
+    /// if it has none, is parsed as the id after removing any non-digits, such as separators.
+    pub ticket_id_pattern: Regex,
+    /// Whether to skip tickets that cannot be parsed, with a warning, instead of failing.
+    pub skip_malformed: bool,
+    /// Whether to also search the full markup of each ticket, including HTML comments, after its
+    /// header and description.
+    pub search_markup: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            flag_pattern: regex_flag().clone(),
+            validate: None,
+            flag_selector: None,
+            tickets_per_user: 0,
+            ticket_id_pattern: regex_ticket_id().clone(),
+            skip_malformed: false,
+            search_markup: false,
+        }
+    }
+}
+
+/// Totals over all requests and downloaded response bodies, optionally limiting the number of
+/// requests.
+#[derive(Debug, Default)]
+pub struct Stats {
+    requests: AtomicUsize,
+    max_requests: Option<usize>,
+    responses: AtomicUsize,
+    bytes: AtomicU64,
+    max_bytes: Option<u64>,
+}
+
+impl Stats {
+    pub fn with_max_requests(max_requests: Option<usize>) -> Self {
+        Self {
+            max_requests,
+            ..Self::default()
+        }
+    }
+
+    /// Stops sending requests once `max_bytes` have been downloaded, if given.
+    pub fn with_max_bytes(self, max_bytes: Option<u64>) -> Self {
+        Self { max_bytes, ..self }
+    }
+
+    /// Counts a request that is about to be sent, failing if the maximum number of requests or
+    /// bytes was already reached.
+    fn start_request(&self) -> Result<(), ScanError> {
+        if self.max_bytes.is_some_and(|max| self.bytes() >= max) {
+            return Err(ScanError::BudgetReached);
+        }
+        let max = self.max_requests.unwrap_or(usize::MAX);
+        self.requests
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .map(drop)
+            .map_err(|_| ScanError::LimitReached)
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, body: &[u8]) {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(body.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Reads the body of `response`, counting its bytes as downloaded, and decodes it like
+    /// [`Response::text`]: according to the charset in `Content-Type` or a byte-order mark,
+    /// defaulting to UTF-8.
+    async fn text(&self, response: Response) -> Result<String, ReqwestError> {
+        let encoding = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(charset)
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+            .unwrap_or(UTF_8);
+        let body = response.bytes().await?;
+        self.record(&body);
+        Ok(encoding.decode(&body).0.into_owned())
+    }
+
+    pub fn responses(&self) -> usize {
+        self.responses.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Describes the totals and throughput, given the duration of the scan.
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let (responses, bytes) = (self.responses(), self.bytes());
+        let average = bytes.checked_div(responses as u64).unwrap_or_default();
+        let rate = bytes as f64 / 1e6 / elapsed.as_secs_f64();
+        format!(
+            "Downloaded {bytes} bytes in {responses} responses ({average} bytes/response, \
+             {rate:.2} MB/s)."
+        )
+    }
+}
+
+/// The charset parameter of a `Content-Type` header value.
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
 }
 
 macro_rules! selector {
@@ -32,6 +283,7 @@ macro_rules! selector {
 }
 
 selector!(selector_meta, ".ticket-card > .ticket-meta");
+selector!(selector_ticket_list, ".ticket-list");
 selector!(selector_ticket, ".ticket-list > .ticket");
 selector!(selector_ticket_id, ".ticket-header > .ticket-id");
 selector!(selector_ticket_header, "h3");
@@ -47,74 +299,260 @@ macro_rules! regex {
 }
 
 regex!(regex_username_header, r"^\s*User:\s*$");
-// WARN: we can't distinguish between leading/trailing whitespace as part of a username or just
-// included in the HTML. In one test case, there was a leading space and no trailing whitespace, so
-// we take this as the format.
-regex!(regex_username_field, r"^ (.*)$");
+regex!(regex_label, r"^\s*\w[\w ]*:\s*$");
 // We don't know the exact format of the flag contents, but we assume it at least doesn't contain
 // any '}' characters.
 regex!(regex_flag, r"flag\{(.*?)\}");
 regex!(regex_ticket_id, r"^\s*Ticket #(\d+)\s*$");
 
+/// Returns the first capture group of `pattern` in `haystack`, or the whole match if it has none.
 fn capture<'a>(pattern: &Regex, haystack: &'a str) -> Option<&'a str> {
     pattern
         .captures(haystack)
-        .map(|c| c.get(1).unwrap().as_str())
+        .map(|c| c.get(1).unwrap_or_else(|| c.get(0).unwrap()).as_str())
 }
 
 // `&Url` does not implement `IntoUrl`, and cloning is likely cheaper than parsing.
 // See #412 in Reqwest.
-pub async fn scan(client: &Client, index_url: Url, id: usize) -> Result<Scan, ScanError> {
-    let ticket_page_url = index_url.join(&format!("ticket/{id}")).unwrap();
-    let ticket_page = client.get(ticket_page_url).send().await?.text().await?;
-    let username = get_username(&Html::parse_document(&ticket_page))?;
-
-    let user_page = client
-        .post(index_url)
-        .form(&[("username", &username)])
-        .send()
-        .await?
-        .text()
+pub async fn scan(
+    client: &Client,
+    index_url: Url,
+    id: usize,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Scan, ScanError> {
+    let username = fetch_username(client, index_url.clone(), id, stats).await?;
+    scan_user(client, index_url, username, config, stats).await
+}
+
+/// The URL of the page of ticket `id`, relative to the index page.
+fn ticket_url(index_url: &Url, id: usize) -> Url {
+    index_url.join(&format!("ticket/{id}")).unwrap()
+}
+
+/// Finds the highest ticket id by doubling and then bisecting, assuming that the ids have no gaps.
+/// Returns 0 if there are no tickets.
+pub async fn find_last_id(
+    client: &Client,
+    index_url: &Url,
+    stats: &Stats,
+) -> Result<usize, ScanError> {
+    let exists = async |id| {
+        stats.start_request()?;
+        let response = client
+            .get(ticket_url(index_url, id))
+            .send()
+            .instrument(info_span!("fetch"))
+            .await?;
+        let status = response.status();
+        stats.text(response).await?;
+        Ok::<_, ScanError>(status != StatusCode::NOT_FOUND)
+    };
+
+    // Invariant: `low` exists (or is 0) and `high` doesn't.
+    let mut low = 0;
+    let mut high = 1;
+    while exists(high).await? {
+        if high == usize::MAX {
+            return Ok(high);
+        }
+        low = high;
+        high = high.saturating_mul(2);
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if exists(mid).await? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Fetches the ticket page to find the username of its author.
+pub async fn fetch_username(
+    client: &Client,
+    index_url: Url,
+    id: usize,
+    stats: &Stats,
+) -> Result<Box<str>, ScanError> {
+    let ticket_page_url = ticket_url(&index_url, id);
+    stats.start_request()?;
+    let ticket_page = async { stats.text(client.get(ticket_page_url).send().await?).await }
+        .instrument(info_span!("fetch"))
         .await?;
-    process_tickets(username, &Html::parse_document(&user_page))
+    let html = info_span!("parse").in_scope(|| Html::parse_document(&ticket_page));
+    if html.select(selector_meta()).next().is_none() {
+        return Err(ScanError::UnexpectedStructure(RawPage(ticket_page.into())));
+    }
+    get_username(&html)
 }
 
-fn get_username(html: &Html) -> Result<Box<str>, ScanError> {
-    let name_field = html
-        .select(selector_meta())
-        .next()
-        .ok_or(ScanError::ElementNotFound)?
-        .text()
-        .skip_while(|h| !regex_username_header().is_match(h))
-        .nth(1)
-        .ok_or(ScanError::UnexpectedFormat)?;
+/// Searches all tickets of a user.
+pub async fn scan_user(
+    client: &Client,
+    index_url: Url,
+    username: Box<str>,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Scan, ScanError> {
+    stats.start_request()?;
+    let (status, user_page) = async {
+        let response = client
+            .post(index_url)
+            .form(&[("username", &username)])
+            .send()
+            .await?;
+        Ok::<_, ReqwestError>((response.status(), stats.text(response).await?))
+    }
+    .instrument(info_span!("fetch"))
+    .await?;
+    let html = info_span!("parse").in_scope(|| Html::parse_document(&user_page));
+    if html.select(selector_ticket_list()).next().is_none() {
+        return Err(ScanError::UnexpectedStructure(RawPage(user_page.into())));
+    }
+    process_tickets(username, status, &html, config)
+}
+
+/// Fetches the ticket page and searches its raw text for the flag, without interpreting the page
+/// structure. Fails with [`ScanError::NotFound`] if the ticket does not exist.
+pub async fn scan_raw(
+    client: &Client,
+    index_url: Url,
+    id: usize,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Option<(StatusCode, FlagMatch)>, ScanError> {
+    let ticket_page_url = ticket_url(&index_url, id);
+    scan_page(client, ticket_page_url, Field::Page, config, stats).await
+}
+
+/// Fetches the index page and searches its raw text for the flag, in case it is shown before any
+/// tickets are looked at.
+pub async fn scan_index(
+    client: &Client,
+    index_url: Url,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Option<(StatusCode, FlagMatch)>, ScanError> {
+    scan_page(client, index_url, Field::IndexPage, config, stats).await
+}
 
-    capture(regex_username_field(), name_field)
-        .map(Into::into)
+async fn scan_page(
+    client: &Client,
+    url: Url,
+    field: Field,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Option<(StatusCode, FlagMatch)>, ScanError> {
+    stats.start_request()?;
+    let (status, page) = async {
+        let response = client.get(url).send().await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(ScanError::NotFound);
+        }
+        Ok((status, stats.text(response).await?))
+    }
+    .instrument(info_span!("fetch"))
+    .await?;
+    let found = info_span!("match")
+        .in_scope(|| FlagMatch::find(&config.flag_pattern, config.validate.as_ref(), field, &page));
+    Ok(found.map(|found| (status, found)))
+}
+
+fn get_username(html: &Html) -> Result<Box<str>, ScanError> {
+    // A page may contain several cards, so use the first meta block that has a username at all.
+    html.select(selector_meta())
+        .find_map(username_in)
         .ok_or(ScanError::UnexpectedFormat)
 }
 
-fn process_tickets(username: Box<str>, html: &Html) -> Result<Scan, ScanError> {
-    let tickets = html.select(selector_ticket()).map(|e| parse_ticket(&e));
+/// Collects the text following the "User:" header of a meta block, up to the next line break or
+/// label such as "Created:".
+// WARN: we can't distinguish between leading/trailing whitespace as part of a username or just
+// included in the HTML, so it is trimmed.
+fn username_in(meta: ElementRef) -> Option<Box<str>> {
+    let header = meta.descendants().find(|n| {
+        n.value()
+            .as_text()
+            .is_some_and(|t| regex_username_header().is_match(t))
+    })?;
+    // The header may be wrapped in markup such as `<strong>`, in which case the username follows
+    // that instead.
+    let header = header
+        .ancestors()
+        .take_while(|n| n.id() != meta.id())
+        .last()
+        .unwrap_or(header);
 
-    let mut ids = Vec::new();
+    let mut username = String::new();
+    for node in header.next_siblings() {
+        let text = match node.value() {
+            Node::Text(text) => text.to_string(),
+            Node::Element(e) if e.name() == "br" => break,
+            Node::Element(_) => ElementRef::wrap(node).unwrap().text().collect(),
+            _ => continue,
+        };
+        // The username may be wrapped in the same markup as the header, so only the text tells
+        // the next header apart.
+        if regex_label().is_match(&text) {
+            break;
+        }
+        username.push_str(&text);
+    }
+
+    let username = username.trim();
+    (!username.is_empty()).then(|| username.into())
+}
+
+fn process_tickets(
+    username: Box<str>,
+    status: StatusCode,
+    html: &Html,
+    config: &ScanConfig,
+) -> Result<Scan, ScanError> {
+    let tickets = html
+        .select(selector_ticket())
+        .map(|e| info_span!("parse").in_scope(|| parse_ticket(&e, config)));
+
+    let pattern = &config.flag_pattern;
+    let validate = config.validate.as_ref();
+    let mut ids = Vec::with_capacity(config.tickets_per_user);
     for ticket in tickets {
         let Ticket {
             id,
             header,
             description,
-        } = ticket?;
-        let pat = regex_flag();
-        if let Some(flag) = capture(pat, &header).or_else(|| capture(pat, &description)) {
-            return Ok(Scan::Success {
-                flag: flag.into(),
-                id,
-            });
+            selected,
+            markup,
+        } = match ticket {
+            Ok(ticket) => ticket,
+            // Its id may be unknown, so it is left for the scan to reach on its own.
+            Err(e) if config.skip_malformed => {
+                eprintln!("Skipping a malformed ticket of user \"{username}\": {e}");
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let found = info_span!("match").in_scope(|| {
+            selected
+                .and_then(|s| FlagMatch::find(pattern, validate, Field::Selected, &s))
+                .or_else(|| FlagMatch::find(pattern, validate, Field::Header, &header))
+                .or_else(|| FlagMatch::find(pattern, validate, Field::Description, &description))
+                .or_else(|| FlagMatch::find(pattern, validate, Field::Markup, &markup?))
+        });
+        if let Some(found) = found {
+            return Ok(Scan::Success { id, status, found });
         } else {
             ids.push(id);
         }
     }
 
+    // A page may list a ticket more than once, e.g. due to overlapping pagination.
+    ids.sort_unstable();
+    ids.dedup();
     Ok(Scan::Failure { username, ids })
 }
 
@@ -122,16 +560,23 @@ struct Ticket {
     id: usize,
     header: String,
     description: String,
+    /// The text of the elements matching the flag selector, if any did.
+    selected: Option<String>,
+    /// The full markup of the ticket, if it is to be searched.
+    markup: Option<String>,
 }
 
-fn parse_ticket(ticket: &ElementRef) -> Result<Ticket, ScanError> {
+fn parse_ticket(ticket: &ElementRef, config: &ScanConfig) -> Result<Ticket, ScanError> {
     let id_inner = ticket
         .select(selector_ticket_id())
         .next()
         .ok_or(ScanError::ElementNotFound)?
         .inner_html();
-    let id = capture(regex_ticket_id(), &id_inner)
+    let id = capture(&config.ticket_id_pattern, &id_inner)
         .ok_or(ScanError::UnexpectedFormat)?
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
         .parse()?;
 
     let header = ticket
@@ -144,10 +589,19 @@ fn parse_ticket(ticket: &ElementRef) -> Result<Ticket, ScanError> {
         .next()
         .ok_or(ScanError::ElementNotFound)?
         .inner_html();
+    let selected = config.flag_selector.as_ref().and_then(|selector| {
+        let mut elements = ticket.select(selector).peekable();
+        elements.peek()?;
+        Some(elements.flat_map(|e| e.text()).collect())
+    });
+
+    let markup = config.search_markup.then(|| ticket.html());
 
     Ok(Ticket {
         id,
         header,
         description,
+        selected,
+        markup,
     })
 }
@@ -1,14 +1,57 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    iter::from_fn,
+    ops::{Bound, Index, RangeBounds, RangeInclusive},
+};
 use thiserror::Error;
 
-// TODO:
-// `BitVec` would have a smaller memory footprint.
-// `BTreeSet` or similar would have better performance for longer sequential skips.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// With the `serde` feature, the sequence can be serialized and restored exactly, e.g. to resume a
+/// scan. The skipped values are stored as the words of the bitmap.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SkipSeq {
+    start: usize,
     passed: usize,
     offset: usize,
-    skip: Vec<bool>,
+    /// A bitmap of which values are skipped, relative to `passed`.
+    skip: Vec<u64>,
+}
+
+/// Like [`SkipSeq`], but stores the skipped values in a set rather than a bitmap. This uses less
+/// memory when few values far apart are skipped, but more when long runs of values are.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SparseSkipSeq {
+    next: usize,
+    skip: BTreeSet<usize>,
+}
+
+/// The operations shared by [`SkipSeq`] and [`SparseSkipSeq`]. Displaying a sequence summarizes
+/// its state on one line.
+pub trait Sequence: Display {
+    /// Returns the next value that was not skipped, or `None` once the values run out at
+    /// `usize::MAX`.
+    fn next(&mut self) -> Option<usize>;
+
+    /// The value that the sequence is at, whether it was skipped or not.
+    fn peek(&self) -> usize;
+
+    fn skip(&mut self, n: usize) -> Result<(), SkipError>;
+
+    /// Whether `n` was skipped and not yet passed.
+    fn is_skipped(&self, n: usize) -> bool;
+
+    /// Skips every value in `values` that was not yet passed, ignoring those already skipped.
+    fn skip_all(&mut self, values: &[usize]) {
+        for &n in values {
+            _ = self.skip(n);
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Error)]
@@ -24,30 +67,131 @@ impl SkipSeq {
         Self::init(start, Vec::new())
     }
 
+    /// Reserves memory for `capacity` values up front.
     pub fn with_capacity(start: usize, capacity: usize) -> Self {
-        Self::init(start, Vec::with_capacity(capacity))
+        Self::init(start, Vec::with_capacity(capacity.div_ceil(WORD_BITS)))
     }
 
-    const fn init(offset: usize, skip: Vec<bool>) -> Self {
+    /// The bitmap starts at `start`, so that no memory is spent on the values before it.
+    const fn init(start: usize, skip: Vec<u64>) -> Self {
         Self {
-            passed: 0,
-            offset,
+            start,
+            passed: start,
+            offset: 0,
             skip,
         }
     }
 
+    /// Forgets every skipped value, keeping the position of the sequence. The allocated capacity
+    /// is kept.
+    pub fn clear(&mut self) {
+        self.passed += self.offset;
+        self.offset = 0;
+        self.skip.clear();
+    }
+
+    /// Resets the sequence to the state of [`new(start)`](Self::new). The allocated capacity is
+    /// kept, so the sequence can be reused without reallocating.
+    pub fn reset(&mut self, start: usize) {
+        self.start = start;
+        self.passed = start;
+        self.offset = 0;
+        self.skip.clear();
+    }
+
+    /// Frees the memory used for values that were already passed, which can never be read again.
+    pub fn trim_start(&mut self) {
+        // Only whole words can be removed without shifting every bit.
+        let words = (self.offset / WORD_BITS).min(self.skip.len());
+        self.skip.drain(..words);
+        self.skip.shrink_to_fit();
+        self.passed += words * WORD_BITS;
+        self.offset -= words * WORD_BITS;
+    }
+
+    /// Frees the memory used past the last skipped value. Unless `keep_skips` is set, the values
+    /// that were skipped but not yet passed are forgotten as well, so they will be returned again.
+    pub fn trim_end(&mut self, keep_skips: bool) {
+        if keep_skips {
+            let len = self
+                .skip
+                .iter()
+                .rposition(|&word| word != 0)
+                .map_or(0, |i| i + 1);
+            self.skip.truncate(len);
+        } else {
+            self.skip.truncate(self.offset.div_ceil(WORD_BITS));
+            if let Some(last) = self.skip.last_mut()
+                && !self.offset.is_multiple_of(WORD_BITS)
+            {
+                *last &= (1 << (self.offset % WORD_BITS)) - 1;
+            }
+        }
+        self.skip.shrink_to_fit();
+    }
+
+    /// Frees the memory reserved past the end of the bitmap, e.g. by
+    /// [`with_capacity`](Self::with_capacity), without forgetting any skipped values.
+    pub fn shrink_to_fit(&mut self) {
+        self.skip.shrink_to_fit();
+    }
+
+    /// The number of values, counting from the first that was not freed, that can be stored
+    /// without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.skip.capacity() * WORD_BITS
+    }
+
     pub const fn peek(&self) -> usize {
         self.passed + self.offset
     }
 
-    pub fn next(&mut self) -> usize {
-        while self.skip.get(self.offset).copied().unwrap_or_default() {
+    /// Returns the next value that was not skipped, or `None` once the values run out at
+    /// `usize::MAX`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<usize> {
+        while self.bit(self.offset) {
             self.offset += 1;
         }
 
-        let res = self.passed + self.offset;
-        self.offset += 1;
-        res
+        let res = self.passed.checked_add(self.offset)?;
+        self.offset = self.offset.checked_add(1)?;
+        Some(res)
+    }
+
+    /// Passes the next `n` values that were not skipped, as if calling [`next`](Self::next) `n`
+    /// times. Skipped values in between don't count towards `n`.
+    pub fn advance(&mut self, n: usize) {
+        for _ in 0..n {
+            if self.next().is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Returns the values that [`next`](Self::next) would return, in order, without advancing the
+    /// sequence.
+    pub fn iter_consumable(&self) -> impl Iterator<Item = usize> + '_ {
+        (self.offset..usize::MAX)
+            .filter(|&i| !self.bit(i))
+            .map_while(|i| self.passed.checked_add(i))
+    }
+
+    /// Returns the values from [`peek`](Self::peek) up to and including `up_to` that were not
+    /// skipped, without advancing the sequence.
+    pub fn remaining(&self, up_to: usize) -> impl Iterator<Item = usize> + '_ {
+        self.iter_consumable().take_while(move |&n| n <= up_to)
+    }
+
+    /// Returns the next `k` values that [`next`](Self::next) would return, or fewer if the values
+    /// run out, without advancing the sequence.
+    pub fn peek_n(&self, k: usize) -> impl Iterator<Item = usize> + '_ {
+        self.iter_consumable().take(k)
+    }
+
+    /// Whether `n` was skipped and not yet passed.
+    pub fn is_skipped(&self, n: usize) -> bool {
+        n >= self.peek() && self.bit(n - self.passed)
     }
 
     pub fn skip(&mut self, n: usize) -> Result<(), SkipError> {
@@ -55,29 +199,439 @@ impl SkipSeq {
             return Err(SkipError::AlreadyPassed(n));
         }
 
-        let i = n - self.passed;
-        if self.skip.get(i).copied().unwrap_or_default() {
+        if self.is_skipped(n) {
             return Err(SkipError::AlreadySkipped(n));
         }
 
-        if i >= self.skip.len() {
-            self.skip.resize(i + 1, false);
+        self.set_bit(n - self.passed, true);
+        Ok(())
+    }
+
+    /// Like [`skip`](Self::skip), but grows the memory to exactly what `n` needs rather than
+    /// amortizing the growth. Prefer this when the largest value to be skipped is known, e.g. when
+    /// skipping it first, and [`skip`](Self::skip) when skipping ever larger values.
+    pub fn skip_exact(&mut self, n: usize) -> Result<(), SkipError> {
+        if n >= self.peek() {
+            let len = (n - self.passed) / WORD_BITS + 1;
+            self.skip.reserve_exact(len.saturating_sub(self.skip.len()));
         }
+        self.skip(n)
+    }
 
-        self.skip[i] = true;
-        Ok(())
+    /// Skips `n` whether it was already skipped or not, returning whether it was, or `None` if it
+    /// was already passed. Unlike [`skip`](Self::skip), a repeated skip is not an error.
+    pub fn replace_skip(&mut self, n: usize) -> Option<bool> {
+        if n < self.peek() {
+            return None;
+        }
+
+        let was_skipped = self.bit(n - self.passed);
+        self.set_bit(n - self.passed, true);
+        Some(was_skipped)
+    }
+
+    /// Skips every value in `values` that was not yet passed, whether it was already skipped or not.
+    /// Memory is reserved once for the largest value, rather than growing with each one.
+    pub fn extend_skips<I: IntoIterator<Item = usize>>(&mut self, values: I) {
+        let next = self.peek();
+        let values: Vec<_> = values.into_iter().filter(|&n| n >= next).collect();
+        let Some(&max) = values.iter().max() else {
+            return;
+        };
+
+        self.reserve_bit(max - self.passed);
+        for n in values {
+            let i = n - self.passed;
+            self.skip[i / WORD_BITS] |= 1 << (i % WORD_BITS);
+        }
+    }
+
+    /// Skips every value in `range` that was not yet passed, whether it was already skipped or not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` has no end.
+    pub fn skip_range(&mut self, range: impl RangeBounds<usize>) {
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.saturating_add(1),
+            Bound::Unbounded => 0,
+        }
+        .max(self.peek());
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n.saturating_add(1),
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => panic!("Cannot skip infinitely many values."),
+        };
+        if start >= end {
+            return;
+        }
+
+        let (start, end) = (start - self.passed, end - self.passed);
+        self.reserve_bit(end - 1);
+        for i in start..end {
+            self.skip[i / WORD_BITS] |= 1 << (i % WORD_BITS);
+        }
+    }
+
+    /// Makes room for skipping values up to and including `max` with
+    /// [`skip_unchecked`](Self::skip_unchecked).
+    pub fn reserve_for(&mut self, max: usize) {
+        if let Some(i) = max.checked_sub(self.passed) {
+            self.reserve_bit(i);
+        }
+    }
+
+    /// Skips `n` without checking whether it was already skipped or passed.
+    ///
+    /// # Safety
+    ///
+    /// `n` must not have been passed, i.e. be at least [`peek`](Self::peek), and room must have
+    /// been made for it with [`reserve_for`](Self::reserve_for), with no call to
+    /// [`clear`](Self::clear), [`reset`](Self::reset) or [`trim_end`](Self::trim_end) since, as
+    /// these shrink the bitmap.
+    pub unsafe fn skip_unchecked(&mut self, n: usize) {
+        let i = n - self.passed;
+        debug_assert!(i / WORD_BITS < self.skip.len(), "No room was made for {n}.");
+        // SAFETY: The caller guarantees that the word is within the stored values.
+        unsafe { *self.skip.get_unchecked_mut(i / WORD_BITS) |= 1 << (i % WORD_BITS) }
+    }
+
+    /// Skips every value that `other` skips, except those that either sequence has passed.
+    /// Values are compared as is, so sequences with different starts can be merged.
+    pub fn union(&mut self, other: &Self) {
+        let skipped = (other.offset..other.skip.len() * WORD_BITS)
+            .filter(|&i| other.bit(i))
+            .map(|i| other.passed + i);
+        for n in skipped {
+            // Values that are already skipped or passed stay that way.
+            _ = self.skip(n);
+        }
+    }
+
+    /// Returns a copy of this sequence that only skips the values that both sequences skip and
+    /// neither has passed. Values are compared as is, like in [`union`](Self::union), and the copy
+    /// is at the same position as this sequence.
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.filter_skips(|n| other.is_skipped(n))
+    }
+
+    /// Returns a copy of this sequence that only skips the values that it skips but `other` does
+    /// not, including those that `other` has already passed. Values are compared as is, like in
+    /// [`union`](Self::union), and the copy is at the same position as this sequence.
+    pub fn difference(&self, other: &Self) -> Self {
+        self.filter_skips(|n| !other.is_skipped(n))
+    }
+
+    /// Copies the sequence, keeping only the skipped values for which `keep` is true.
+    fn filter_skips(&self, keep: impl Fn(usize) -> bool) -> Self {
+        let mut res = self.clone();
+        for i in self.offset..self.skip.len() * WORD_BITS {
+            if self.bit(i) && !keep(self.passed + i) {
+                res.set_bit(i, false);
+            }
+        }
+        res
+    }
+
+    /// Undoes skipping `n`, so that it is returned again. Returns whether `n` was skipped, which is
+    /// never the case once it was passed.
+    pub fn unskip(&mut self, n: usize) -> bool {
+        if !self.is_skipped(n) {
+            return false;
+        }
+
+        self.set_bit(n - self.passed, false);
+        true
+    }
+
+    /// The number of values that were skipped and not yet passed.
+    pub fn count_skipped(&self) -> usize {
+        let Some((first, rest)) = self
+            .skip
+            .get(self.offset / WORD_BITS..)
+            .and_then(|words| words.split_first())
+        else {
+            return 0;
+        };
+        let first = (first >> (self.offset % WORD_BITS)).count_ones() as usize;
+        first + rest.iter().map(|w| w.count_ones() as usize).sum::<usize>()
+    }
+
+    /// The number of values that are stored but neither skipped nor passed. Values past those
+    /// are not skipped either, but take no memory.
+    pub fn count_remaining_in_buffer(&self) -> usize {
+        (self.skip.len() * WORD_BITS).saturating_sub(self.offset) - self.count_skipped()
+    }
+
+    /// The number of values that the bitmap holds ahead of the sequence without them being
+    /// skipped, i.e. what [`next`](Self::next) will return before it runs past the bitmap. This is
+    /// the same as [`count_remaining_in_buffer`](Self::count_remaining_in_buffer). Values past the
+    /// bitmap are not counted, since the sequence never runs out of them before `usize::MAX`.
+    pub fn len(&self) -> usize {
+        self.count_remaining_in_buffer()
+    }
+
+    /// Whether [`len`](Self::len) is 0, as for a new sequence or one whose values in the bitmap
+    /// are all skipped or passed. An empty sequence still returns values past the bitmap.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The runs of values that were skipped and not yet passed, in order.
+    pub fn skipped_ranges(&self) -> impl Iterator<Item = RangeInclusive<usize>> + '_ {
+        let mut i = self.offset;
+        let end = self.skip.len() * WORD_BITS;
+        from_fn(move || {
+            while i < end && !self.bit(i) {
+                i += 1;
+            }
+            if i >= end {
+                return None;
+            }
+            let first = i;
+            while i < end && self.bit(i) {
+                i += 1;
+            }
+            Some(self.passed + first..=self.passed + i - 1)
+        })
+    }
+
+    /// Whether the value at index `i` of the bitmap is skipped.
+    fn bit(&self, i: usize) -> bool {
+        self.skip
+            .get(i / WORD_BITS)
+            .is_some_and(|word| word >> (i % WORD_BITS) & 1 == 1)
+    }
+
+    fn set_bit(&mut self, i: usize, skipped: bool) {
+        self.reserve_bit(i);
+        let word = &mut self.skip[i / WORD_BITS];
+        if skipped {
+            *word |= 1 << (i % WORD_BITS);
+        } else {
+            *word &= !(1 << (i % WORD_BITS));
+        }
+    }
+
+    /// Grows the bitmap to include index `i`.
+    fn reserve_bit(&mut self, i: usize) {
+        let len = i / WORD_BITS + 1;
+        if len > self.skip.len() {
+            self.skip.resize(len, 0);
+        }
     }
 
     // TODO:
     // Rename constructors? 4 options instead of 2?
-    // `trim_start` as `passed` is redundant without it.
-    // `trim_end`, possibly with option to ignore existent skips.
-    // `skip_unchecked(n)`
-    // `skip(n)` with exact reservation.
-    // `is_skipped(n)`, possibly as `impl Index` and `skip` as `impl IndexMut`.
-    // `impl Iterator`
-    // `union(Self)`
-    // `unskip(n)`, possibly under a different name.
-    // `advance(n)`.
     // Fine-grained control over leading/trailing/total capacity.
 }
+
+/// Shows the skipped values as runs rather than the whole bitmap.
+impl Debug for SkipSeq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("SkipSeq")
+            .field("start", &self.start)
+            .field("next", &self.peek())
+            .field("skipped", &self.skipped_ranges().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Summarizes the state as the next value, the number of values passed since the start and the
+/// number of values skipped but not yet passed, e.g. `SkipSeq { next: 42, passed: 41, skipped: 3 }`.
+impl Display for SkipSeq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "SkipSeq {{ next: {}, passed: {}, skipped: {} }}",
+            self.peek(),
+            self.peek() - self.start,
+            self.count_skipped()
+        )
+    }
+}
+
+/// Whether `n` was skipped and not yet passed, as in [`SkipSeq::is_skipped`]. Values that were
+/// passed or lie past the bitmap read as `false`.
+///
+/// There is no `IndexMut`, since a bitmap has no `bool` to hand out a reference to; use
+/// [`skip`](SkipSeq::skip) and [`unskip`](SkipSeq::unskip) instead of `seq[n] = true` and
+/// `seq[n] = false`.
+impl Index<usize> for SkipSeq {
+    type Output = bool;
+
+    fn index(&self, n: usize) -> &bool {
+        if self.is_skipped(n) { &true } else { &false }
+    }
+}
+
+/// Yields the values of [`SkipSeq::next`], ending only at `usize::MAX`.
+///
+/// Implemented for `&mut SkipSeq` rather than `SkipSeq`, so that `seq.skip(n)` still refers to
+/// [`SkipSeq::skip`] instead of [`Iterator::skip`].
+impl Iterator for &mut SkipSeq {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        SkipSeq::next(self)
+    }
+}
+
+/// Builds a sequence in which the given values are skipped. It starts at 1 like ticket ids, or at 0
+/// if 0 is among the values, and reserves exactly enough memory for the largest value.
+impl FromIterator<usize> for SkipSeq {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let skipped: Vec<_> = iter.into_iter().collect();
+        let start = skipped.iter().min().map_or(1, |&min| min.min(1));
+        let mut seq = Self::new(start);
+        if let Some(&max) = skipped.iter().max() {
+            seq.reserve_for(max);
+        }
+        for n in skipped {
+            // Duplicates are already skipped.
+            _ = seq.skip(n);
+        }
+        seq
+    }
+}
+
+impl Sequence for SkipSeq {
+    fn next(&mut self) -> Option<usize> {
+        SkipSeq::next(self)
+    }
+
+    fn peek(&self) -> usize {
+        SkipSeq::peek(self)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), SkipError> {
+        SkipSeq::skip(self, n)
+    }
+
+    fn is_skipped(&self, n: usize) -> bool {
+        SkipSeq::is_skipped(self, n)
+    }
+
+    fn skip_all(&mut self, values: &[usize]) {
+        self.extend_skips(values.iter().copied());
+    }
+}
+
+impl SparseSkipSeq {
+    pub const fn new(start: usize) -> Self {
+        Self {
+            next: start,
+            skip: BTreeSet::new(),
+        }
+    }
+}
+
+/// Summarizes the state as the next value and the number of values skipped but not yet passed,
+/// e.g. `SparseSkipSeq { next: 42, skipped: 3 }`.
+impl Display for SparseSkipSeq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "SparseSkipSeq {{ next: {}, skipped: {} }}",
+            self.next,
+            self.skip.len()
+        )
+    }
+}
+
+impl Sequence for SparseSkipSeq {
+    fn next(&mut self) -> Option<usize> {
+        // Skipped values are forgotten once passed, since they can never be read again.
+        while self.skip.remove(&self.next) {
+            self.next = self.next.checked_add(1)?;
+        }
+
+        let res = self.next;
+        self.next = self.next.checked_add(1)?;
+        Some(res)
+    }
+
+    fn peek(&self) -> usize {
+        self.next
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), SkipError> {
+        if n < self.next {
+            return Err(SkipError::AlreadyPassed(n));
+        }
+
+        if !self.skip.insert(n) {
+            return Err(SkipError::AlreadySkipped(n));
+        }
+        Ok(())
+    }
+
+    fn is_skipped(&self, n: usize) -> bool {
+        n >= self.next && self.skip.contains(&n)
+    }
+}
+
+/// Runs a sequence backwards, from `last` down to 1, e.g. to scan the most recent tickets first.
+/// Values are stored by their distance from `last`, so the inner sequence should start at 0.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Descending<S> {
+    inner: S,
+    last: usize,
+}
+
+impl<S: Sequence> Descending<S> {
+    pub const fn new(inner: S, last: usize) -> Self {
+        Self { inner, last }
+    }
+
+    /// The position of `n` in the inner sequence, or `None` if `n` is out of range.
+    fn index(&self, n: usize) -> Option<usize> {
+        (1..=self.last).contains(&n).then(|| self.last - n)
+    }
+}
+
+/// Summarizes the state as the next value and that of the inner sequence, e.g.
+/// `Descending { next: 42, inner: SkipSeq { next: 8, passed: 8, skipped: 0 } }`.
+impl<S: Sequence> Display for Descending<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "Descending {{ next: {}, inner: {} }}",
+            self.peek(),
+            self.inner
+        )
+    }
+}
+
+impl<S: Sequence> Sequence for Descending<S> {
+    fn next(&mut self) -> Option<usize> {
+        let i = self.inner.next()?;
+        (i < self.last).then(|| self.last - i)
+    }
+
+    /// 0 once the values have run out.
+    fn peek(&self) -> usize {
+        self.last.saturating_sub(self.inner.peek())
+    }
+
+    /// Values above `last` count as passed, since the sequence started past them.
+    fn skip(&mut self, n: usize) -> Result<(), SkipError> {
+        match self.index(n) {
+            Some(i) => self.inner.skip(i).map_err(|e| match e {
+                SkipError::AlreadySkipped(_) => SkipError::AlreadySkipped(n),
+                SkipError::AlreadyPassed(_) => SkipError::AlreadyPassed(n),
+            }),
+            None => Err(SkipError::AlreadyPassed(n)),
+        }
+    }
+
+    fn is_skipped(&self, n: usize) -> bool {
+        self.index(n).is_some_and(|i| self.inner.is_skipped(i))
+    }
+
+    fn skip_all(&mut self, values: &[usize]) {
+        let indices: Vec<_> = values.iter().filter_map(|&n| self.index(n)).collect();
+        self.inner.skip_all(&indices);
+    }
+}
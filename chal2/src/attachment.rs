@@ -0,0 +1,35 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// The shortest run of base64 that is taken as an attachment rather than e.g. a word.
+const MIN_BLOB_LEN: usize = 24;
+
+fn regex_blob() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(&format!(r"[A-Za-z0-9+/]{{{MIN_BLOB_LEN},}}={{0,2}}")).unwrap())
+}
+
+/// Decodes the base64 blobs embedded in `text` and returns their text. Blobs that decode to more
+/// than `max_bytes` are ignored.
+pub fn attachment_texts(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut texts = Vec::new();
+    for blob in regex_blob().find_iter(text) {
+        // Checked before decoding, so that a huge blob is never held twice.
+        let padding = blob
+            .as_str()
+            .bytes()
+            .rev()
+            .take_while(|&b| b == b'=')
+            .count();
+        if (blob.len() / 4 * 3).saturating_sub(padding) > max_bytes {
+            eprintln!("Skipping an attachment of over {max_bytes} bytes.");
+            continue;
+        }
+        let Ok(bytes) = BASE64_STANDARD.decode(blob.as_str()) else {
+            continue;
+        };
+        texts.push(String::from_utf8_lossy(&bytes).into());
+    }
+    texts
+}
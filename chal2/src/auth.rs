@@ -0,0 +1,94 @@
+use crate::scan::ScanError;
+use reqwest::{
+    Client, ClientBuilder, StatusCode, Url,
+    header::{AUTHORIZATION, HeaderMap, HeaderValue},
+};
+
+/// A way of getting access to the tickets, applied before scanning.
+pub trait AuthProvider {
+    /// The headers to send with every request, e.g. credentials.
+    fn headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+
+    /// Adjusts the client before it is built, by default to send [`headers`](Self::headers).
+    fn configure(&self, builder: ClientBuilder) -> ClientBuilder {
+        builder.default_headers(self.headers())
+    }
+
+    /// Authenticates the built client with the server at `base`. Returns the status and body of
+    /// the page that authenticating leads to, if a page was fetched.
+    // The client is only ever authenticated before any tasks are spawned, so the future need not
+    // be `Send`.
+    #[allow(async_fn_in_trait)]
+    async fn authenticate(
+        &self,
+        client: &Client,
+        base: &Url,
+    ) -> Result<Option<(StatusCode, String)>, ScanError>;
+}
+
+/// Scans without authenticating.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Anonymous;
+
+impl AuthProvider for Anonymous {
+    async fn authenticate(
+        &self,
+        _: &Client,
+        _: &Url,
+    ) -> Result<Option<(StatusCode, String)>, ScanError> {
+        Ok(None)
+    }
+}
+
+/// Gets a session key by submitting the login form on the index page, keeping it as a cookie.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FormLogin {
+    pub username: Box<str>,
+}
+
+impl Default for FormLogin {
+    fn default() -> Self {
+        // The username isn't relevant, but has to be nonempty.
+        Self {
+            username: "name".into(),
+        }
+    }
+}
+
+impl AuthProvider for FormLogin {
+    async fn authenticate(
+        &self,
+        client: &Client,
+        base: &Url,
+    ) -> Result<Option<(StatusCode, String)>, ScanError> {
+        let response = client
+            .post(base.clone())
+            .form(&[("username", &self.username)])
+            .send()
+            .await?;
+        Ok(Some((response.status(), response.text().await?)))
+    }
+}
+
+/// Sends a bearer token with every request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bearer(pub Box<str>);
+
+impl AuthProvider for Bearer {
+    fn headers(&self) -> HeaderMap {
+        let mut value =
+            HeaderValue::try_from(format!("Bearer {}", self.0)).expect("Invalid bearer token.");
+        value.set_sensitive(true);
+        HeaderMap::from_iter([(AUTHORIZATION, value)])
+    }
+
+    async fn authenticate(
+        &self,
+        _: &Client,
+        _: &Url,
+    ) -> Result<Option<(StatusCode, String)>, ScanError> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// How often workers check whether a probe has finished.
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How many times a request fails, or the circuit opens while it waits, before its unhealthy result
+/// is returned anyway.
+const MAX_RETRIES: usize = 5;
+
+/// Pauses all requests while the server seems unhealthy.
+///
+/// Once more than `threshold` of the last `window` requests have failed, the circuit opens and no
+/// requests are sent for `cooldown`. A single probe request is then sent: if it succeeds, the
+/// circuit closes again, otherwise it stays open for another cooldown.
+#[derive(Debug)]
+pub struct Breaker {
+    state: Mutex<State>,
+    threshold: f64,
+    window: usize,
+    cooldown: Duration,
+    /// How many times the circuit has opened.
+    openings: AtomicUsize,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Requests are sent freely. Holds whether each of the most recent requests succeeded.
+    Closed(VecDeque<bool>),
+    Open {
+        until: Instant,
+    },
+    /// A probe is in flight.
+    HalfOpen,
+}
+
+impl Breaker {
+    pub fn new(threshold: f64, window: usize, cooldown: Duration) -> Self {
+        Self {
+            state: Mutex::new(State::Closed(VecDeque::with_capacity(window))),
+            threshold,
+            window,
+            cooldown,
+            openings: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sends `request` once the circuit allows it, repeating it for as long as `healthy` rejects
+    /// the result. Gives up after [`MAX_RETRIES`], since waiting behind the probes of other requests
+    /// would otherwise be unbounded.
+    pub async fn call<F: Future>(
+        &self,
+        request: impl Fn() -> F,
+        healthy: impl Fn(&F::Output) -> bool,
+    ) -> F::Output {
+        let openings = self.openings.load(Ordering::Relaxed);
+        let mut failures = 0;
+        loop {
+            let probe = self.acquire().await;
+            let result = request().await;
+            let ok = healthy(&result);
+            self.record(ok, probe);
+            failures += usize::from(!ok);
+            let opened = self.openings.load(Ordering::Relaxed) - openings;
+            if ok || failures.max(opened) >= MAX_RETRIES {
+                return result;
+            }
+        }
+    }
+
+    /// Waits until a request may be sent. Returns whether that request is the probe.
+    async fn acquire(&self) -> bool {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                match *state {
+                    State::Closed(_) => return false,
+                    State::Open { until } => {
+                        let now = Instant::now();
+                        if now >= until {
+                            *state = State::HalfOpen;
+                            return true;
+                        }
+                        until - now
+                    }
+                    State::HalfOpen => PROBE_POLL_INTERVAL,
+                }
+            };
+            sleep(wait).await;
+        }
+    }
+
+    fn record(&self, ok: bool, probe: bool) {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed(outcomes) => {
+                if outcomes.len() == self.window {
+                    outcomes.pop_front();
+                }
+                outcomes.push_back(ok);
+
+                let failures = outcomes.iter().filter(|ok| !**ok).count();
+                if outcomes.len() == self.window
+                    && failures as f64 / self.window as f64 > self.threshold
+                {
+                    eprintln!(
+                        "{failures} of the last {} requests failed, pausing for {:?}.",
+                        self.window, self.cooldown
+                    );
+                    *state = self.open();
+                }
+            }
+            State::HalfOpen if probe => {
+                if ok {
+                    eprintln!("Server recovered, resuming.");
+                    *state = State::Closed(VecDeque::with_capacity(self.window));
+                } else {
+                    eprintln!("Server still unhealthy, pausing for {:?}.", self.cooldown);
+                    *state = self.open();
+                }
+            }
+            // Requests sent before the circuit opened don't say anything about the server now.
+            State::Open { .. } | State::HalfOpen => {}
+        }
+    }
+
+    fn open(&self) -> State {
+        self.openings.fetch_add(1, Ordering::Relaxed);
+        State::Open {
+            until: Instant::now() + self.cooldown,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use reqwest::{
+    Url,
+    cookie::{CookieStore, Jar},
+    header::{COOKIE, HeaderMap, HeaderValue},
+};
+use std::{fmt::Write as _, sync::Arc};
+
+/// Builds `curl` commands that repeat a request with the headers and cookies of the client.
+#[derive(Debug)]
+pub struct CurlCommand {
+    /// The headers that the client sends with every request.
+    headers: HeaderMap,
+    jar: Arc<Jar>,
+    show_secrets: bool,
+}
+
+impl CurlCommand {
+    /// Unless `show_secrets` is set, sensitive headers and cookies are replaced by a placeholder.
+    pub const fn new(headers: HeaderMap, jar: Arc<Jar>, show_secrets: bool) -> Self {
+        Self {
+            headers,
+            jar,
+            show_secrets,
+        }
+    }
+
+    /// A command that sends a GET request to `url`, ready to paste into a POSIX shell.
+    pub fn get(&self, url: &Url) -> String {
+        let mut headers = self.headers.clone();
+        if let Some(mut cookies) = self.jar.cookies(url) {
+            // The session key is as good as a password.
+            cookies.set_sensitive(true);
+            headers.insert(COOKIE, cookies);
+        }
+
+        let mut command = format!("curl -X GET {}", quote(url.as_str()));
+        for (name, value) in &headers {
+            _ = write!(command, " -H {}", quote(&self.header(name.as_str(), value)));
+        }
+        command
+    }
+
+    fn header(&self, name: &str, value: &HeaderValue) -> String {
+        if value.is_sensitive() && !self.show_secrets {
+            return format!("{name}: <redacted>");
+        }
+        // Values that aren't visible ASCII can't be pasted faithfully either way.
+        format!("{name}: {}", String::from_utf8_lossy(value.as_bytes()))
+    }
+}
+
+/// Quotes `s` as a single shell word.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
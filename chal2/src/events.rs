@@ -0,0 +1,55 @@
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// A format for reporting events as they happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum EventFormat {
+    /// One JSON object per line.
+    Ndjson,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    Request {
+        id: usize,
+    },
+    Page {
+        url: &'a str,
+    },
+    Error {
+        message: String,
+    },
+    FlagFound {
+        /// `None` if the flag was found on the landing page rather than in a ticket.
+        id: Option<usize>,
+        flag: &'a str,
+        field: String,
+        context: &'a str,
+        status: u16,
+    },
+    Summary {
+        requests: usize,
+        responses: usize,
+        bytes: u64,
+        seconds: f64,
+    },
+}
+
+impl EventFormat {
+    /// Writes an event to stdout, flushing it right away.
+    pub fn emit(self, event: &Event) {
+        match self {
+            Self::Ndjson => {
+                // Locked so that the events of concurrent workers can't interleave.
+                let mut stdout = io::stdout().lock();
+                _ = serde_json::to_writer(&mut stdout, event);
+                _ = writeln!(stdout);
+                _ = stdout.flush();
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::scan::Ticket;
+use std::{
+    fmt::Write as _,
+    fs::{create_dir_all, write},
+    io::Result as IoResult,
+    path::{Path, PathBuf},
+};
+
+/// Writes the tickets as a standalone HTML page to `index.html` in `dir`, returning its path.
+pub fn export_site(dir: &Path, tickets: &[Ticket]) -> IoResult<PathBuf> {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html>\n",
+        "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Tickets</title>\n",
+        "<style>body { font-family: sans-serif; } .ticket { border-bottom: 1px solid #ccc; }</style>\n",
+        "</head>\n<body>\n<h1>Tickets</h1>\n<ul>\n",
+    ));
+    for Ticket { id, subject, .. } in tickets {
+        _ = writeln!(
+            html,
+            "<li><a href=\"#ticket-{id}\">#{id}: {}</a></li>",
+            escape(subject)
+        );
+    }
+    html.push_str("</ul>\n");
+    for Ticket {
+        id,
+        subject,
+        description,
+        ..
+    } in tickets
+    {
+        _ = writeln!(
+            html,
+            "<div class=\"ticket\" id=\"ticket-{id}\">\n<h2>#{id}: {}</h2>\n<pre>{}</pre>\n</div>",
+            escape(subject),
+            escape(description)
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+
+    create_dir_all(dir)?;
+    let path = dir.join("index.html");
+    write(&path, html)?;
+    Ok(path)
+}
+
+/// Writes the ids to `path` as sorted, merged ranges, one per line, e.g. `3-7`.
+pub fn export_ids(path: &Path, mut ids: Vec<usize>) -> IoResult<()> {
+    ids.sort_unstable();
+    ids.dedup();
+    let mut text = String::new();
+    let mut ids = ids.into_iter().peekable();
+    while let Some(first) = ids.next() {
+        let mut last = first;
+        while let Some(id) = ids.next_if_eq(&(last + 1)) {
+            last = id;
+        }
+        _ = if first == last {
+            writeln!(text, "{first}")
+        } else {
+            writeln!(text, "{first}-{last}")
+        };
+    }
+    write(path, text)
+}
+
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
@@ -0,0 +1,27 @@
+use reqwest::{Client, Url};
+use std::{sync::Arc, time::Duration};
+use tokio::time::{MissedTickBehavior, interval};
+
+/// Requests `url` every `period` for as long as it runs, warning when the server stops responding
+/// and noting when it responds again. Each probe may take at most `period`.
+pub async fn probe_health(client: Arc<Client>, url: Url, period: Duration) {
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut healthy = true;
+    loop {
+        ticks.tick().await;
+
+        let result = client.get(url.clone()).timeout(period).send().await;
+        match result.and_then(|r| r.error_for_status()) {
+            Ok(_) if !healthy => {
+                eprintln!("Server is responding again.");
+                healthy = true;
+            }
+            Err(e) if healthy => {
+                eprintln!("Server failed a health probe: {e}");
+                healthy = false;
+            }
+            _ => {}
+        }
+    }
+}
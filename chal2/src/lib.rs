@@ -0,0 +1,15 @@
+//! Scans the tickets of a help desk API for a flag. The command-line interface is behind the
+//! default `cli` feature; without it, only the scanning itself is built.
+
+pub mod attachment;
+pub mod auth;
+pub mod breaker;
+pub mod curl;
+pub mod events;
+pub mod export;
+pub mod health;
+pub mod pacing;
+pub mod processor;
+pub mod profile;
+pub mod scan;
+pub mod transform;
@@ -1,14 +1,44 @@
-use clap::Parser;
-use reqwest::{Client, Url, redirect::Policy};
-use std::sync::{Arc, atomic::AtomicUsize};
-use tokio::{spawn, sync::mpsc::channel};
+use clap::{Parser, value_parser};
+use rand::random;
+use regex::Regex;
+use reqwest::{Client, StatusCode, Url, cookie::Jar, header::HeaderMap, redirect::Policy};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, stdin},
+    select,
+    signal::ctrl_c,
+    spawn,
+    sync::mpsc::channel,
+    time::sleep,
+};
+use tracing::subscriber::set_global_default;
 
-mod scan;
-
-use scan::*;
+use chal2::{
+    auth::{Anonymous, AuthProvider, Bearer, FormLogin},
+    breaker::Breaker,
+    curl::CurlCommand,
+    events::{Event, EventFormat},
+    export::{export_ids, export_site},
+    health::probe_health,
+    pacing::TokenBucket,
+    processor::*,
+    profile::ChromeTrace,
+    scan::*,
+    transform::{Transform, apply_all},
+};
 
 const BUFFER_SIZE: usize = 16;
 const NUM_THREADS: usize = 64;
+const PREVIEW_WIDTH: usize = 60;
+const BREAKER_WINDOW: usize = 32;
 
 #[derive(Parser)]
 struct Cli {
@@ -17,54 +47,666 @@ struct Cli {
     #[arg(short, long)]
     /// Prints information about progress.
     verbose: bool,
+    #[arg(long, value_name = "PATH")]
+    /// Fetches tickets from a paginated list endpoint, following `Link` headers, instead of
+    /// guessing ids.
+    follow_links: Option<Box<str>>,
+    #[arg(long, value_name = "N")]
+    /// Prints a random sample of N tickets instead of searching for the flag.
+    sample: Option<usize>,
+    #[arg(long)]
+    /// Searches the raw response bodies for the flag instead of parsing them.
+    raw: bool,
+    #[arg(long)]
+    /// The seed for any randomized behavior. Generated if omitted.
+    seed: Option<u64>,
+    #[arg(short, long)]
+    /// Reads commands from stdin to scan individual tickets.
+    interactive: bool,
+    #[arg(long, value_name = "DIR")]
+    /// Saves all tickets as a browsable HTML page in DIR instead of searching for the flag.
+    export: Option<PathBuf>,
+    #[arg(long, value_name = "N")]
+    /// Reports the prefixes of flag-like patterns (`prefix{...}`) in a random sample of N tickets
+    /// instead of searching for the flag.
+    detect_format: Option<usize>,
+    #[arg(long, value_name = "N")]
+    /// Reports how many tickets in a random sample of N contain a flag-like pattern in each field
+    /// instead of searching for the flag.
+    compare_fields: Option<usize>,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    /// Keeps scanning past missing tickets as long as they are within N ids of the highest
+    /// existing ticket.
+    gap_tolerance: usize,
+    #[arg(long)]
+    /// Prints where and when the flag was found, for use in a write-up.
+    report_flag: bool,
+    #[arg(long)]
+    /// Scans anonymously, without first getting a session key.
+    no_login: bool,
+    #[arg(long, value_name = "TOKEN", conflicts_with = "no_login")]
+    /// Authenticates by sending TOKEN as a bearer token with every request, instead of getting a
+    /// session key.
+    bearer_token: Option<Box<str>>,
+    #[arg(long, value_name = "PATH", conflicts_with = "follow_links")]
+    /// Fetches all tickets in one request from an endpoint serving them as an array, falling back
+    /// to guessing ids if it is unavailable.
+    all_tickets: Option<Box<str>>,
+    #[arg(long, value_name = "RATE")]
+    /// Pauses all requests whenever more than RATE (between 0 and 1) of the recent requests have
+    /// failed, retrying the failed tickets once the server recovers.
+    circuit_breaker: Option<f64>,
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    /// How long to pause for when the circuit breaker trips.
+    breaker_cooldown: u64,
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["follow_links", "all_tickets"])]
+    /// Fetches tickets from a list endpoint paginated by a cursor in each page, instead of
+    /// guessing ids.
+    follow_cursor: Option<Box<str>>,
+    #[arg(long, value_name = "POINTER", default_value = "/next_cursor")]
+    /// The JSON pointer to the cursor within each page, for `--follow-cursor`.
+    cursor_field: Box<str>,
+    #[arg(long, value_name = "POINTER", default_value = "/tickets")]
+    /// The JSON pointer to the tickets within each page, for `--follow-cursor`.
+    cursor_items: Box<str>,
+    #[arg(long, value_name = "TRANSFORMS", value_delimiter = ',')]
+    /// Transformations to apply to the flag in order before printing it, e.g. `trim,base64`.
+    flag_transform: Vec<Transform>,
+    #[arg(long, value_name = "TIME", value_parser = parse_rfc3339)]
+    /// Only scans tickets created at or after TIME (RFC 3339). Tickets without a creation time are
+    /// always scanned.
+    since: Option<OffsetDateTime>,
+    #[arg(long, value_name = "N")]
+    /// Stops scanning after sending N requests for tickets.
+    max_requests: Option<usize>,
+    #[arg(long)]
+    /// Only accepts tickets in responses with a 2xx status. Has no effect with `--raw` or when
+    /// fetching lists of tickets.
+    require_success_status: bool,
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_field)]
+    /// Only accepts tickets with a top-level field NAME equal to VALUE. Has no effect with `--raw`
+    /// or when fetching lists of tickets.
+    success_field: Option<(Box<str>, Box<str>)>,
+    #[arg(long, value_name = "SECS", value_parser = value_parser!(u64).range(1..))]
+    /// Requests the index page every SECS seconds during the scan, warning if the server stops
+    /// responding.
+    health_probe_interval: Option<u64>,
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    /// The pattern of the flag, `flag\{(.*?)\}` by default. Its first capture group, or the whole
+    /// match if it has none, is taken as the flag.
+    flag_pattern: Option<Regex>,
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        conflicts_with_all = [
+            "verbose",
+            "interactive",
+            "fetch_only",
+            "export",
+            "detect_format",
+            "compare_fields",
+            "sample",
+        ]
+    )]
+    /// Reports requests, errors, the flag and a summary as events while scanning, instead of
+    /// printing them as text. Only for searching for the flag, since other output is not an event.
+    events: Option<EventFormat>,
+    #[arg(long, value_name = "PATH")]
+    /// Writes the ids of the tickets that were scanned without finding the flag to PATH, as
+    /// ranges such as `3-7`, one per line.
+    dump_scanned: Option<PathBuf>,
+    #[arg(long, value_name = "R", value_parser = parse_rate)]
+    /// Sends at most R requests per second, after an initial burst of `--burst` requests.
+    rate: Option<f64>,
+    #[arg(long, value_name = "B", requires = "rate", default_value_t = 1, value_parser = value_parser!(u64).range(1..))]
+    /// How many requests may be sent at once before `--rate` applies.
+    burst: u64,
+    #[arg(long, value_name = "PATH")]
+    /// Also fetches and searches the comments of each ticket from PATH, relative to the index
+    /// page, with `{id}` in place of the ticket id, e.g. `api/tickets/{id}/comments`. Has no
+    /// effect with `--raw` or when fetching tickets from a list endpoint.
+    comments_path: Option<Box<str>>,
+    #[arg(long, conflicts_with_all = ["follow_links", "all_tickets", "follow_cursor"])]
+    /// Only fetches the tickets, without parsing or searching them, and reports the request
+    /// throughput.
+    fetch_only: bool,
+    #[arg(long, conflicts_with_all = ["follow_links", "all_tickets", "follow_cursor"])]
+    /// Finds the highest ticket id first and scans downwards from it, for when the flag is likely
+    /// in a recent ticket.
+    prioritize_recent: bool,
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    /// Only accepts flags whose whole match, e.g. `flag{...}`, matches REGEX, reporting the others
+    /// as rejected candidates. Useful with a loose `--flag-pattern`.
+    validate: Option<Regex>,
+    #[arg(long, conflicts_with_all = ["events", "follow_links", "all_tickets", "follow_cursor"])]
+    /// Prints a `curl` command that repeats the request in which the flag was found, with the
+    /// same headers and cookies.
+    curl: bool,
+    #[arg(long, requires = "curl")]
+    /// Includes credentials such as the session cookie in the `curl` command instead of redacting
+    /// them.
+    show_secrets: bool,
+    #[arg(long, value_name = "SECS")]
+    /// Starts the workers one by one over SECS seconds rather than all at once, so that the
+    /// server isn't hit by a burst of requests. Only applies when guessing ids.
+    ramp_up: Option<u64>,
+    #[arg(long, value_name = "PATH")]
+    /// Records how long fetching, parsing and matching take and writes the trace to PATH in the
+    /// Chrome trace event format, e.g. for Perfetto or flamegraph tools.
+    profile: Option<PathBuf>,
+    #[arg(long)]
+    /// Also searches base64 attachments in tickets for the flag.
+    scan_attachments: bool,
+    #[arg(long, value_name = "BYTES", requires = "scan_attachments", default_value_t = 1 << 20)]
+    /// Ignores attachments that decode to more than BYTES, for `--scan-attachments`.
+    max_attachment_bytes: usize,
+    #[arg(long, value_name = "N")]
+    /// Stops scanning once N bytes of responses have been downloaded.
+    max_bytes: Option<u64>,
+    #[arg(
+        long,
+        value_name = "ID",
+        default_value_t = 1,
+        conflicts_with = "prioritize_recent"
+    )]
+    /// The first ticket id to scan.
+    start_id: usize,
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = value_parser!(u64).range(1..), conflicts_with = "prioritize_recent")]
+    /// Only scans every Nth ticket id from `--start-id`, for when the ids are known to be spaced
+    /// out, e.g. N = 10 for multiples of 10. `--gap-tolerance` still counts single ids.
+    step: u64,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), ScanError> {
-    let Cli { index_url, verbose } = Cli::parse();
-    let client = Client::builder()
-        .cookie_store(true)
-        .redirect(Policy::limited(1))
-        .build()
-        .expect("Failed to initialize client.");
+    let Cli {
+        index_url,
+        verbose,
+        follow_links,
+        sample,
+        raw,
+        seed,
+        interactive,
+        export,
+        detect_format,
+        compare_fields,
+        gap_tolerance,
+        report_flag,
+        no_login,
+        bearer_token,
+        all_tickets,
+        circuit_breaker,
+        breaker_cooldown,
+        follow_cursor,
+        cursor_field,
+        cursor_items,
+        flag_transform,
+        since,
+        max_requests,
+        require_success_status,
+        success_field,
+        health_probe_interval,
+        flag_pattern,
+        events,
+        dump_scanned,
+        rate,
+        burst,
+        comments_path,
+        fetch_only,
+        prioritize_recent,
+        validate,
+        curl,
+        show_secrets,
+        ramp_up,
+        profile,
+        scan_attachments,
+        max_attachment_bytes,
+        max_bytes,
+        start_id,
+        step,
+    } = Cli::parse();
+    let trace = profile.as_ref().map(|_| {
+        let trace = Arc::new(ChromeTrace::default());
+        set_global_default(Arc::clone(&trace)).expect("Failed to start profiling.");
+        trace
+    });
+    let seed = seed.unwrap_or_else(random);
+    let sample_size = sample.or(detect_format).or(compare_fields);
+    if sample_size.is_some() {
+        // Printed so that a run can be reproduced.
+        println!("Using seed {seed}.");
+    }
 
-    // The username isn't relevant, but has to be nonempty.
-    client
-        .post(index_url.clone())
-        .form(&[("username", "name")])
-        .send()
-        .await
-        .expect("Failed to get session key.");
+    let jar = Arc::new(Jar::default());
+    let (client, headers, landing) = match bearer_token {
+        Some(token) => connect(&Bearer(token), &index_url, Arc::clone(&jar)).await,
+        None if no_login => connect(&Anonymous, &index_url, Arc::clone(&jar)).await,
+        None => connect(&FormLogin::default(), &index_url, Arc::clone(&jar)).await,
+    };
+    let curl = curl.then(|| CurlCommand::new(headers, jar, show_secrets));
 
-    let index_url = Arc::new(index_url);
-    let client = Arc::new(client);
+    let default = ScanConfig::default();
+    let config = Arc::new(ScanConfig {
+        flag_pattern: flag_pattern.unwrap_or(default.flag_pattern),
+        validate,
+        max_attachment_bytes: scan_attachments.then_some(max_attachment_bytes),
+        success: SuccessCheck {
+            status: require_success_status,
+            field: success_field,
+        },
+        verbose,
+        events,
+        comments_path,
+    });
+
+    if interactive {
+        repl(&client, &index_url, &config).await;
+        return Ok(());
+    }
 
+    // The flag may be right on the landing page, in which case no tickets need to be scanned.
+    let searching = !fetch_only
+        && export.is_none()
+        && detect_format.is_none()
+        && compare_fields.is_none()
+        && sample.is_none();
+    if searching
+        && let Some((status, body)) = &landing
+        && let Some(found) = search_landing_page(body, &config)
+    {
+        match events {
+            Some(events) => events.emit(&Event::FlagFound {
+                id: None,
+                flag: &found.flag,
+                field: found.field.to_string(),
+                context: &found.context,
+                status: status.as_u16(),
+            }),
+            None => print_flag(None, *status, &found, report_flag, &flag_transform),
+        }
+        flush_output();
+        return Ok(());
+    }
+
+    let client = Arc::new(client);
     let (tx, rx) = channel(BUFFER_SIZE);
-    let counter = Arc::new(AtomicUsize::new(1));
-
-    let mut handles = Vec::with_capacity(NUM_THREADS);
-    for _ in 0..NUM_THREADS {
-        let client = Arc::clone(&client);
-        let index_url = Arc::clone(&index_url);
-        let counter = Arc::clone(&counter);
-        let tx = tx.clone();
-        handles.push(spawn(async move {
-            fetch_tickets(tx, client, index_url, counter, verbose).await
-        }));
-    }
-
-    match process_tickets(rx).await? {
-        Scan::Success { flag, id } => {
-            println!("Found flag: {flag} (ticket #{id})");
+
+    let mut handles = Vec::with_capacity(NUM_THREADS + 1);
+    if let Some(secs) = health_probe_interval {
+        let period = Duration::from_secs(secs);
+        handles.push(spawn(probe_health(
+            Arc::clone(&client),
+            index_url.clone(),
+            period,
+        )));
+    }
+    let pacing = rate.map(|rate| TokenBucket::new(burst as usize, rate));
+    let stats = Arc::new(
+        Stats::with_max_requests(max_requests)
+            .with_max_bytes(max_bytes)
+            .with_pacing(pacing),
+    );
+    let start = Instant::now();
+    let ids = if prioritize_recent {
+        let last = find_last_id(&client, &index_url, &config, &stats).await?;
+        if verbose {
+            println!("Scanning down from ticket {last}.");
         }
-        Scan::Failure => {
-            eprintln!("Failed to find flag.");
+        Ids::descending(last)
+    } else if let Some(size) = sample_size
+        && follow_links.is_none()
+        && follow_cursor.is_none()
+        && all_tickets.is_none()
+    {
+        // With the ids bounded, only the sampled tickets need to be fetched. Otherwise, every
+        // ticket is fetched and the sample drawn from them as they arrive.
+        let last = find_last_id(&client, &index_url, &config, &stats).await?;
+        if verbose {
+            println!("Sampling {size} of the tickets up to {last}.");
+        }
+        Ids::sample(last, size, seed)
+    } else {
+        Ids::new(start_id, gap_tolerance).with_step(step as usize)
+    };
+    let ids = Arc::new(ids);
+
+    let mut all = None;
+    if let Some(path) = all_tickets {
+        let url = index_url.join(&path).expect("Invalid array endpoint.");
+        all = fetch_all(&client, url, &stats).await?;
+        if all.is_none() {
+            eprintln!("Array endpoint unavailable, guessing ids instead.");
         }
     }
 
+    let pages = match (follow_links, follow_cursor) {
+        (Some(path), _) => Some((path, Pagination::Link)),
+        (_, Some(path)) => Some((
+            path,
+            Pagination::Cursor {
+                items: cursor_items,
+                cursor: cursor_field,
+            },
+        )),
+        (None, None) => None,
+    };
+
+    let paginated = pages.is_some() || all.is_some();
+    if let Some(tickets) = all {
+        if verbose {
+            println!("Fetched {} tickets at once.", tickets.len());
+        }
+        handles.push(spawn(send_tickets(tx, tickets)));
+    } else if let Some((path, pagination)) = pages {
+        let page_url = index_url.join(&path).expect("Invalid list endpoint.");
+        handles.push(spawn(fetch_pages(
+            tx,
+            client,
+            page_url,
+            pagination,
+            Arc::clone(&stats),
+            Arc::clone(&config),
+        )));
+    } else {
+        let index_url = Arc::new(index_url.clone());
+        let breaker = circuit_breaker.map(|threshold| {
+            let cooldown = Duration::from_secs(breaker_cooldown);
+            Arc::new(Breaker::new(threshold, BREAKER_WINDOW, cooldown))
+        });
+        let ramp_up = Duration::from_secs(ramp_up.unwrap_or(0));
+        for i in 0..NUM_THREADS {
+            let delay = ramp_up * i as u32 / NUM_THREADS as u32;
+            let client = Arc::clone(&client);
+            let index_url = Arc::clone(&index_url);
+            let ids = Arc::clone(&ids);
+            let config = Arc::clone(&config);
+            let stats = Arc::clone(&stats);
+            let breaker = breaker.clone();
+            let tx = tx.clone();
+            handles.push(spawn(async move {
+                sleep(delay).await;
+                if fetch_only {
+                    fetch_bytes(tx, client, index_url, ids, stats, config).await
+                } else if raw {
+                    fetch_raw_tickets(tx, client, index_url, ids, stats, breaker, config).await
+                } else {
+                    fetch_tickets(tx, client, index_url, ids, stats, breaker, config).await
+                }
+            }));
+        }
+        // Only the workers should keep the channel open, or `process_tickets` never finishes.
+        drop(tx);
+    }
+
+    let mut scanned = RecordScanned::new(FlagMatcher(
+        &config.flag_pattern,
+        config.validate.as_ref(),
+        config.max_attachment_bytes,
+    ));
+    let consume = async {
+        if fetch_only {
+            // Nothing but errors is sent, so this only waits for the workers to finish.
+            process_tickets(rx, &mut Collector::default()).await?;
+            let (requests, secs) = (stats.requests(), start.elapsed().as_secs_f64());
+            println!(
+                "Sent {requests} requests in {secs:.2} s ({:.1} requests/s).",
+                requests as f64 / secs
+            );
+        } else if let Some(dir) = export {
+            let mut collector = Collector::default();
+            process_tickets(rx, &mut CreatedSince::new(since, &mut collector)).await?;
+            let tickets = collector.into_tickets();
+            let path = export_site(&dir, &tickets).expect("Failed to export tickets.");
+            println!("Exported {} tickets to {}.", tickets.len(), path.display());
+        } else if let Some(size) = detect_format {
+            let mut sampler = Sampler::new(size, seed);
+            process_tickets(rx, &mut CreatedSince::new(since, &mut sampler)).await?;
+            let tickets = sampler.into_sample();
+            let prefixes = flag_prefixes(&tickets);
+            if prefixes.is_empty() {
+                eprintln!("No flag-like patterns in {} tickets.", tickets.len());
+            }
+            for (prefix, count) in prefixes {
+                println!("{count:>6}  {prefix}{{...}}");
+            }
+        } else if let Some(size) = compare_fields {
+            let mut sampler = Sampler::new(size, seed);
+            process_tickets(rx, &mut CreatedSince::new(since, &mut sampler)).await?;
+            let tickets = sampler.into_sample();
+            for (field, count) in field_matches(&tickets) {
+                println!("{count:>6}/{}  {field}", tickets.len());
+            }
+        } else if let Some(size) = sample {
+            let mut sampler = Sampler::new(size, seed);
+            process_tickets(rx, &mut CreatedSince::new(since, &mut sampler)).await?;
+            for ticket in sampler.into_sample() {
+                println!("{}", ticket.preview(PREVIEW_WIDTH));
+            }
+        } else {
+            match process_tickets(rx, &mut CreatedSince::new(since, &mut scanned)).await? {
+                Scan::Success { id, status, found } => match events {
+                    Some(events) => events.emit(&Event::FlagFound {
+                        id: Some(id),
+                        flag: &found.flag,
+                        field: found.field.to_string(),
+                        context: &found.context,
+                        status: status.as_u16(),
+                    }),
+                    None => {
+                        print_flag(Some(id), status, &found, report_flag, &flag_transform);
+                        if let Some(curl) = &curl {
+                            let url = match (&found.field, &config.comments_path) {
+                                (Field::Comments, Some(path)) => comments_url(&index_url, path, id),
+                                _ => ticket_url(&index_url, id),
+                            };
+                            println!("{}", curl.get(&url));
+                        }
+                    }
+                },
+                Scan::Failure => {
+                    eprintln!("Failed to find flag.");
+                }
+            }
+        }
+        Ok::<_, ScanError>(())
+    };
+
+    // An error ends the scan, but what was scanned until then is still reported before it is
+    // returned.
+    let (interrupted, res) = select! {
+        res = consume => {
+            if let (Err(e), Some(events)) = (&res, events) {
+                events.emit(&Event::Error {
+                    message: e.to_string(),
+                });
+            }
+            (false, res)
+        }
+        _ = shutdown_signal() => (true, Ok(())),
+    };
+
+    // The result should be written out before anything else can go wrong, e.g. when piped.
+    flush_output();
     for h in handles {
         h.abort();
     }
 
-    Ok(())
+    if let (Some(path), Some(trace)) = (profile, trace) {
+        trace.write(&path).expect("Failed to write profile.");
+    }
+    if let Some(path) = dump_scanned {
+        export_ids(&path, scanned.into_ids()).expect("Failed to dump scanned ids.");
+    }
+    if stats.limited() {
+        eprintln!("Stopped after {} requests.", stats.requests());
+    }
+    if stats.budget_spent() {
+        eprintln!("Stopped after downloading {} bytes.", stats.bytes());
+    }
+    if interrupted {
+        if paginated {
+            eprintln!("Interrupted.");
+        } else {
+            let requested = ids.requested();
+            eprintln!("Interrupted after requesting {requested} tickets.");
+        }
+    }
+    if let Some(events) = events {
+        events.emit(&Event::Summary {
+            requests: stats.requests(),
+            responses: stats.responses(),
+            bytes: stats.bytes(),
+            seconds: start.elapsed().as_secs_f64(),
+        });
+    } else if verbose {
+        println!("{}", stats.summary(start.elapsed()));
+    }
+
+    flush_output();
+    res
+}
+
+/// Builds a client that keeps cookies in `jar` and authenticates it with `auth`. Also returns the
+/// headers that the client sends with every request and the page that authenticating led to.
+async fn connect(
+    auth: &impl AuthProvider,
+    index_url: &Url,
+    jar: Arc<Jar>,
+) -> (Client, HeaderMap, Option<(StatusCode, String)>) {
+    let builder = Client::builder()
+        .cookie_provider(jar)
+        .redirect(Policy::limited(1));
+    let client = auth
+        .configure(builder)
+        .build()
+        .expect("Failed to initialize client.");
+    let landing = auth
+        .authenticate(&client, index_url)
+        .await
+        .expect("Failed to authenticate.");
+    (client, auth.headers(), landing)
+}
+
+fn flush_output() {
+    _ = io::stdout().flush();
+    _ = io::stderr().flush();
+}
+
+/// Prints a found flag, optionally over several lines describing where and when it was found. `id`
+/// is `None` if it was found on the landing page rather than in a ticket.
+fn print_flag(
+    id: Option<usize>,
+    status: StatusCode,
+    found: &FlagMatch,
+    report: bool,
+    transforms: &[Transform],
+) {
+    let transformed = if transforms.is_empty() {
+        None
+    } else {
+        apply_all(transforms, &found.flag)
+            .inspect_err(|e| eprintln!("Failed to transform flag: {e}"))
+            .ok()
+    };
+
+    if !report {
+        let location = id.map_or("landing page".into(), |id| format!("ticket #{id}"));
+        match transformed {
+            Some(transformed) => println!(
+                "Found flag: {transformed} ({location}, originally {})",
+                found.flag
+            ),
+            None => println!("Found flag: {} ({location})", found.flag),
+        }
+        return;
+    }
+
+    let time = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .expect("Failed to format time.");
+    println!("Flag:     {}", found.flag);
+    if let Some(transformed) = transformed {
+        println!("Decoded:  {transformed}");
+    }
+    if let Some(id) = id {
+        println!("Ticket:   #{id}");
+    }
+    println!("Field:    {}", found.field);
+    println!("Context:  {:?}", found.context);
+    println!("Status:   {status}");
+    println!("Found at: {time}");
+}
+
+fn parse_field(s: &str) -> Result<(Box<str>, Box<str>), &'static str> {
+    s.split_once('=')
+        .map(|(name, value)| (name.into(), value.into()))
+        .ok_or("expected NAME=VALUE")
+}
+
+fn parse_rate(s: &str) -> Result<f64, &'static str> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|rate| *rate > 0.0 && rate.is_finite())
+        .ok_or("expected a positive number")
+}
+
+fn parse_rfc3339(s: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    OffsetDateTime::parse(s, &Rfc3339)
+}
+
+/// Completes once the process is asked to stop, by Ctrl-C or, on Unix, `SIGTERM`.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install signal handler.");
+        select! {
+            _ = ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        _ = ctrl_c().await;
+    }
+}
+
+/// Reads commands from stdin until EOF or `quit`, reusing the session of `client`.
+async fn repl(client: &Client, index_url: &Url, config: &ScanConfig) {
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        print!("> ");
+        _ = io::stdout().flush();
+        let Some(line) = lines.next_line().await.expect("Failed to read input.") else {
+            break;
+        };
+
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("scan"), Some(id), None) => {
+                let Ok(id) = id.parse() else {
+                    eprintln!("Invalid ticket id: {id}");
+                    continue;
+                };
+                match fetch_ticket(client, index_url, id, config, &Stats::default()).await {
+                    Ok(Some(ticket)) => {
+                        println!("{}", ticket.preview(PREVIEW_WIDTH));
+                        let (pattern, validate) = (&config.flag_pattern, config.validate.as_ref());
+                        let found = ticket.flag(pattern, validate).or_else(|| {
+                            let max_bytes = config.max_attachment_bytes?;
+                            ticket.attachment_flag(pattern, validate, max_bytes)
+                        });
+                        if let Some(found) = found {
+                            println!("Found flag: {} (ticket #{id})", found.flag);
+                        }
+                    }
+                    Ok(None) => println!("Ticket #{id} not found."),
+                    Err(e) => eprintln!("{e}"),
+                }
+            }
+            (Some("quit" | "exit"), None, None) => break,
+            (None, ..) => {}
+            _ => eprintln!("Commands: scan <id>, quit"),
+        }
+    }
 }
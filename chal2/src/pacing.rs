@@ -0,0 +1,51 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Spaces out requests with a token bucket, allowing bursts of up to `burst` requests followed by
+/// `rate` requests per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    state: Mutex<State>,
+    burst: f64,
+    rate: f64,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Negative once tokens have been promised to waiting requests.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// Starts out full, so the first `burst` requests are sent immediately.
+    pub fn new(burst: usize, rate: f64) -> Self {
+        let burst = burst as f64;
+        Self {
+            state: Mutex::new(State {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+            burst,
+            rate,
+        }
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * self.rate;
+            state.tokens = (state.tokens + refill).min(self.burst);
+            state.refilled = now;
+            // Taking the token up front keeps waiting requests in order.
+            state.tokens -= 1.0;
+            Duration::from_secs_f64((-state.tokens).max(0.0) / self.rate)
+        };
+        sleep(wait).await;
+    }
+}
@@ -0,0 +1,158 @@
+use crate::scan::{FlagMatch, Ticket};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use regex::Regex;
+use std::collections::BinaryHeap;
+use time::OffsetDateTime;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ProcessOutcome {
+    FlagFound(FlagMatch),
+    Continue,
+    /// Ends the scan without a flag.
+    Stop,
+}
+
+/// Decides what to do with each scanned ticket.
+pub trait TicketProcessor {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome;
+}
+
+impl<P: TicketProcessor + ?Sized> TicketProcessor for &mut P {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        (**self).process(ticket)
+    }
+}
+
+/// Stops at the first ticket containing a match of the flag pattern, optionally only counting
+/// matches that also match the validation pattern. If an attachment size is given, attachments up
+/// to that size are searched after the rest of each ticket.
+#[derive(Clone, Copy, Debug)]
+pub struct FlagMatcher<'a>(pub &'a Regex, pub Option<&'a Regex>, pub Option<usize>);
+
+impl TicketProcessor for FlagMatcher<'_> {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        let found = ticket.flag(self.0, self.1).or_else(|| {
+            let max_bytes = self.2?;
+            ticket.attachment_flag(self.0, self.1, max_bytes)
+        });
+        match found {
+            Some(found) => ProcessOutcome::FlagFound(found),
+            None => ProcessOutcome::Continue,
+        }
+    }
+}
+
+/// Keeps every ticket.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Collector {
+    tickets: Vec<Ticket>,
+}
+
+impl Collector {
+    /// The collected tickets, sorted by id.
+    pub fn into_tickets(mut self) -> Vec<Ticket> {
+        self.tickets.sort_unstable_by_key(|t| t.id);
+        self.tickets
+    }
+}
+
+impl TicketProcessor for Collector {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        self.tickets.push(ticket.clone());
+        ProcessOutcome::Continue
+    }
+}
+
+/// Draws a uniformly random sample of tickets.
+///
+/// Each ticket is assigned a random key derived from the seed and its id, and the tickets with the
+/// smallest keys are kept. Unlike classic reservoir sampling, this doesn't depend on the order in
+/// which the workers deliver tickets, so a given seed always yields the same sample.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    size: usize,
+    seed: u64,
+    sample: BinaryHeap<(u64, Ticket)>,
+}
+
+impl Sampler {
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self {
+            size,
+            seed,
+            sample: BinaryHeap::with_capacity(size + 1),
+        }
+    }
+
+    /// The sampled tickets, sorted by id.
+    pub fn into_sample(self) -> Vec<Ticket> {
+        let mut sample: Vec<_> = self.sample.into_iter().map(|(_, t)| t).collect();
+        sample.sort_unstable_by_key(|t| t.id);
+        sample
+    }
+}
+
+impl TicketProcessor for Sampler {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        let key = StdRng::seed_from_u64(self.seed ^ ticket.id as u64).random();
+        self.sample.push((key, ticket.clone()));
+        if self.sample.len() > self.size {
+            self.sample.pop();
+        }
+        ProcessOutcome::Continue
+    }
+}
+
+/// Passes on only the tickets created at or after `since`, if given. Tickets without a creation
+/// time are always passed on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CreatedSince<P> {
+    since: Option<OffsetDateTime>,
+    inner: P,
+}
+
+impl<P> CreatedSince<P> {
+    pub fn new(since: Option<OffsetDateTime>, inner: P) -> Self {
+        Self { since, inner }
+    }
+}
+
+impl<P: TicketProcessor> TicketProcessor for CreatedSince<P> {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        match (self.since, ticket.created_at) {
+            (Some(since), Some(created_at)) if created_at < since => ProcessOutcome::Continue,
+            _ => self.inner.process(ticket),
+        }
+    }
+}
+
+/// Records the ids of the tickets that `inner` found no flag in.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RecordScanned<P> {
+    ids: Vec<usize>,
+    inner: P,
+}
+
+impl<P> RecordScanned<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            ids: Vec::new(),
+            inner,
+        }
+    }
+
+    /// The recorded ids, in the order they were scanned.
+    pub fn into_ids(self) -> Vec<usize> {
+        self.ids
+    }
+}
+
+impl<P: TicketProcessor> TicketProcessor for RecordScanned<P> {
+    fn process(&mut self, ticket: &Ticket) -> ProcessOutcome {
+        let outcome = self.inner.process(ticket);
+        if outcome == ProcessOutcome::Continue {
+            self.ids.push(ticket.id);
+        }
+        outcome
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    cell::Cell,
+    fmt::Write as _,
+    fs::write,
+    io::Result as IoResult,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+use tracing::{
+    Event, Metadata, Subscriber,
+    span::{Attributes, Id, Record},
+};
+
+/// Records when spans are entered and exited, to be written out as a trace in the Chrome trace
+/// event format, which can be opened in e.g. Perfetto or converted to a flamegraph.
+#[derive(Debug)]
+pub struct ChromeTrace {
+    start: Instant,
+    /// The names of the spans, indexed by their id minus one.
+    names: Mutex<Vec<&'static str>>,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TraceEvent {
+    name: &'static str,
+    begin: bool,
+    micros: f64,
+    thread: u64,
+}
+
+/// A small number identifying the current thread, since `ThreadId` can't be converted to one.
+fn thread_number() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static NUMBER: Cell<u64> = const { Cell::new(0) };
+    }
+
+    NUMBER.with(|n| {
+        if n.get() == 0 {
+            n.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        n.get()
+    })
+}
+
+impl Default for ChromeTrace {
+    /// Starts the clock of the trace.
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            names: Mutex::new(Vec::new()),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ChromeTrace {
+    fn push(&self, span: &Id, begin: bool) {
+        let micros = self.start.elapsed().as_secs_f64() * 1e6;
+        let name = self.names.lock().unwrap()[span.into_u64() as usize - 1];
+        self.events.lock().unwrap().push(TraceEvent {
+            name,
+            begin,
+            micros,
+            thread: thread_number(),
+        });
+    }
+
+    /// Writes the spans recorded so far to `path` as JSON.
+    pub fn write(&self, path: &Path) -> IoResult<()> {
+        let events = self.events.lock().unwrap();
+        let mut json = String::from("{\"traceEvents\":[\n");
+        for (i, event) in events.iter().enumerate() {
+            let separator = if i + 1 < events.len() { "," } else { "" };
+            // Span names are identifiers, so they need no escaping.
+            _ = writeln!(
+                json,
+                "{{\"name\":\"{}\",\"ph\":\"{}\",\"ts\":{:.3},\"pid\":1,\"tid\":{}}}{separator}",
+                event.name,
+                if event.begin { "B" } else { "E" },
+                event.micros,
+                event.thread,
+            );
+        }
+        json.push_str("]}\n");
+        write(path, json)
+    }
+}
+
+impl Subscriber for ChromeTrace {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut names = self.names.lock().unwrap();
+        names.push(span.metadata().name());
+        Id::from_u64(names.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        self.push(span, true);
+    }
+
+    fn exit(&self, span: &Id) {
+        self.push(span, false);
+    }
+}
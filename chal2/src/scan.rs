@@ -1,37 +1,416 @@
-use bytes::Bytes;
+use crate::{
+    attachment::attachment_texts,
+    breaker::Breaker,
+    events::{Event, EventFormat},
+    pacing::TokenBucket,
+    processor::{ProcessOutcome, TicketProcessor},
+};
+use encoding_rs::{Encoding, UTF_8};
+use rand::{SeedableRng, rngs::StdRng, seq::index::sample};
 use regex::Regex;
-use reqwest::{Client, Error as ReqwestError, Url};
+use reqwest::{
+    Client, Error as ReqwestError, Response, StatusCode, Url,
+    header::{CONTENT_TYPE, LINK},
+};
 use serde::Deserialize;
-use serde_json::from_slice as json_from_slice;
-use std::sync::{
-    Arc, OnceLock,
-    atomic::{AtomicUsize, Ordering},
+use serde_json::{Value, from_str as json_from_str};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::Range,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 use thiserror::Error;
+use time::OffsetDateTime;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{Instrument, info_span};
 
 const BUFFER_CAPACITY_WARNING: usize = 4;
+const CONTEXT_CHARS: usize = 20;
 
 pub enum Scan {
-    Success { flag: Box<str>, id: usize },
+    Success {
+        id: usize,
+        status: StatusCode,
+        found: FlagMatch,
+    },
     Failure,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Subject,
+    Description,
+    Comments,
+    Attachment,
+    /// The page that logging in leads to.
+    LandingPage,
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(match self {
+            Self::Subject => "subject",
+            Self::Description => "description",
+            Self::Comments => "comments",
+            Self::Attachment => "attachment",
+            Self::LandingPage => "landing page",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlagMatch {
+    pub flag: Box<str>,
+    pub field: Field,
+    /// The flag along with up to `CONTEXT_CHARS` characters on either side.
+    pub context: Box<str>,
+    /// The byte range of the whole match, e.g. `flag{...}`, within the field.
+    pub range: Range<usize>,
+}
+
+impl FlagMatch {
+    /// Finds the first match of `pattern` whose whole text also matches `validate`, if given.
+    fn find(
+        pattern: &Regex,
+        validate: Option<&Regex>,
+        field: Field,
+        haystack: &str,
+    ) -> Option<Self> {
+        let captures = pattern.captures_iter(haystack).find(|c| {
+            let whole = c.get(0).unwrap().as_str();
+            let valid = validate.is_none_or(|v| v.is_match(whole));
+            if !valid {
+                eprintln!("Rejected flag candidate: {whole}");
+            }
+            valid
+        })?;
+        let whole = captures.get(0).unwrap();
+
+        let start = haystack[..whole.start()]
+            .char_indices()
+            .rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        let end = haystack[whole.end()..]
+            .char_indices()
+            .nth(CONTEXT_CHARS)
+            .map_or(haystack.len(), |(i, _)| whole.end() + i);
+
+        Some(Self {
+            flag: captures.get(1).unwrap_or(whole).as_str().into(),
+            field,
+            context: haystack[start..end].into(),
+            range: whole.range(),
+        })
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ScanError {
     #[error("{0}")]
     Io(#[from] ReqwestError),
     #[error("Unknown JSON schema: {0:?}")]
-    UnknownSchema(Bytes),
+    UnknownSchema(Box<str>),
     #[error("Server responded with an error: {0}")]
     Response(Box<str>),
+    /// A server error status with a body that is neither a ticket nor an error, e.g. from a proxy.
+    #[error("Server responded with {0}.")]
+    Status(StatusCode),
+    #[error("Reached the maximum number of requests or bytes.")]
+    LimitReached,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
 pub struct Ticket {
-    id: usize,
-    subject: Box<str>,
-    description: Box<str>,
+    pub id: usize,
+    pub subject: Box<str>,
+    pub description: Box<str>,
+    /// The status of the response the ticket was received in.
+    #[serde(skip)]
+    pub status: StatusCode,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+    /// The raw body of the comments endpoint of the ticket, if it was fetched and exists.
+    #[serde(skip)]
+    pub comments: Option<Box<str>>,
+}
+
+impl Ticket {
+    /// Searches the subject, description and comments for the flag.
+    /// Matches whose whole text doesn't match `validate`, if given, are passed over.
+    pub fn flag(&self, pattern: &Regex, validate: Option<&Regex>) -> Option<FlagMatch> {
+        let _span = info_span!("match").entered();
+        FlagMatch::find(pattern, validate, Field::Subject, &self.subject)
+            .or_else(|| FlagMatch::find(pattern, validate, Field::Description, &self.description))
+            .or_else(|| {
+                let comments = self.comments.as_deref()?;
+                FlagMatch::find(pattern, validate, Field::Comments, comments)
+            })
+    }
+
+    /// Searches the base64 attachments in the subject and description for the flag, ignoring
+    /// attachments of more than `max_bytes`.
+    pub fn attachment_flag(
+        &self,
+        pattern: &Regex,
+        validate: Option<&Regex>,
+        max_bytes: usize,
+    ) -> Option<FlagMatch> {
+        [&self.subject, &self.description]
+            .into_iter()
+            .flat_map(|field| attachment_texts(field, max_bytes))
+            .find_map(|text| FlagMatch::find(pattern, validate, Field::Attachment, &text))
+    }
+
+    /// Formats the ticket on a single line, truncating each field to at most `width` characters.
+    pub fn preview(&self, width: usize) -> String {
+        fn truncate(field: &str, width: usize) -> String {
+            let mut chars = field
+                .chars()
+                .map(|c| if c.is_whitespace() { ' ' } else { c });
+            let mut res: String = chars.by_ref().take(width).collect();
+            if chars.next().is_some() {
+                res.push('…');
+            }
+            res
+        }
+
+        format!(
+            "#{}: {} | {}",
+            self.id,
+            truncate(&self.subject, width),
+            truncate(&self.description, width)
+        )
+    }
+}
+
+/// Hands out ticket ids to the workers and decides when they've run out of tickets.
+#[derive(Debug)]
+pub struct Ids {
+    start: usize,
+    next: AtomicUsize,
+    highest_found: AtomicUsize,
+    gap_tolerance: usize,
+    /// Whether the ids count down from `start` to 1 instead of up.
+    descending: bool,
+    /// The difference between consecutive ids.
+    step: usize,
+    /// If given, only these ids are handed out, in order, and `next` indexes them instead.
+    list: Option<Vec<usize>>,
+}
+
+impl Ids {
+    /// Missing tickets within `gap_tolerance` ids of the highest existing ticket are assumed to be
+    /// gaps rather than the end of the tickets.
+    pub const fn new(start: usize, gap_tolerance: usize) -> Self {
+        Self {
+            start,
+            next: AtomicUsize::new(start),
+            highest_found: AtomicUsize::new(0),
+            gap_tolerance,
+            descending: false,
+            step: 1,
+            list: None,
+        }
+    }
+
+    /// Only hands out every `step`th id from the start, for when the ids are known to be spaced
+    /// out, e.g. multiples of 10.
+    pub fn with_step(self, step: usize) -> Self {
+        Self { step, ..self }
+    }
+
+    /// Counts down from `start` to 1, so that missing tickets never end the scan.
+    pub fn descending(start: usize) -> Self {
+        Self {
+            descending: true,
+            ..Self::new(start, 0)
+        }
+    }
+
+    /// Draws `size` distinct ids from `1..=last` as seeded by `seed`, e.g. to sample the tickets
+    /// without fetching all of them. Missing tickets never end the scan.
+    pub fn sample(last: usize, size: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut list: Vec<_> = sample(&mut rng, last, size.min(last))
+            .into_iter()
+            .map(|i| i + 1)
+            .collect();
+        list.sort_unstable();
+        Self {
+            list: Some(list),
+            ..Self::new(0, 0)
+        }
+    }
+
+    /// Hands out the next id, or `None` once they run out at `usize::MAX`, or 0 if descending.
+    pub fn next(&self) -> Option<usize> {
+        if let Some(list) = &self.list {
+            return list.get(self.next.fetch_add(1, Ordering::SeqCst)).copied();
+        }
+        self.next
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                if self.descending {
+                    id.checked_sub(self.step)
+                } else {
+                    id.checked_add(self.step)
+                }
+            })
+            .ok()
+            .filter(|&id| id > 0)
+    }
+
+    /// The number of ids handed out so far.
+    pub fn requested(&self) -> usize {
+        if let Some(list) = &self.list {
+            return self.next.load(Ordering::SeqCst).min(list.len());
+        }
+        self.next.load(Ordering::SeqCst).abs_diff(self.start) / self.step
+    }
+
+    fn found(&self, id: usize) {
+        self.highest_found.fetch_max(id, Ordering::SeqCst);
+    }
+
+    /// Whether ticket `id` not existing means there are no more tickets to fetch.
+    fn is_end(&self, id: usize) -> bool {
+        if self.descending || self.list.is_some() {
+            return false;
+        }
+        // Tickets still in flight may exist but not have been found yet, so this is only a
+        // heuristic. In particular, any missing ticket ends the scan if there's no tolerance.
+        id > self
+            .highest_found
+            .load(Ordering::SeqCst)
+            .saturating_add(self.gap_tolerance)
+    }
+}
+
+/// Totals over all requests and downloaded response bodies, optionally limiting the number and
+/// rate of requests.
+#[derive(Debug, Default)]
+pub struct Stats {
+    requests: AtomicUsize,
+    max_requests: Option<usize>,
+    /// Whether a request was refused due to the maximum.
+    limited: AtomicBool,
+    responses: AtomicUsize,
+    bytes: AtomicU64,
+    max_bytes: Option<u64>,
+    /// Whether a request was refused due to the maximum number of bytes.
+    budget_spent: AtomicBool,
+    pacing: Option<TokenBucket>,
+}
+
+impl Stats {
+    pub fn with_max_requests(max_requests: Option<usize>) -> Self {
+        Self {
+            max_requests,
+            ..Self::default()
+        }
+    }
+
+    /// Stops sending requests once `max_bytes` have been downloaded, if given.
+    pub fn with_max_bytes(self, max_bytes: Option<u64>) -> Self {
+        Self { max_bytes, ..self }
+    }
+
+    /// Spaces out requests with `pacing`, if given.
+    pub fn with_pacing(self, pacing: Option<TokenBucket>) -> Self {
+        Self { pacing, ..self }
+    }
+
+    /// Counts a request that is about to be sent, failing if the maximum number of requests or
+    /// bytes was already reached, and waits until the pacing allows it.
+    async fn start_request(&self) -> Result<(), ScanError> {
+        if self.max_bytes.is_some_and(|max| self.bytes() >= max) {
+            self.budget_spent.store(true, Ordering::Relaxed);
+            return Err(ScanError::LimitReached);
+        }
+        let max = self.max_requests.unwrap_or(usize::MAX);
+        self.requests
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .map_err(|_| {
+                self.limited.store(true, Ordering::Relaxed);
+                ScanError::LimitReached
+            })?;
+        if let Some(pacing) = &self.pacing {
+            pacing.acquire().await;
+        }
+        Ok(())
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Whether the scan was cut short by the maximum number of requests.
+    pub fn limited(&self) -> bool {
+        self.limited.load(Ordering::Relaxed)
+    }
+
+    /// Whether the scan was cut short by the maximum number of bytes.
+    pub fn budget_spent(&self) -> bool {
+        self.budget_spent.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, body: impl AsRef<[u8]>) {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        self.bytes
+            .fetch_add(body.as_ref().len() as u64, Ordering::Relaxed);
+    }
+
+    /// Reads the body of `response`, counting its bytes as downloaded, and decodes it like
+    /// [`Response::text`]: according to the charset in `Content-Type` or a byte-order mark,
+    /// defaulting to UTF-8.
+    async fn text(&self, response: Response) -> Result<String, ReqwestError> {
+        let encoding = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(charset)
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+            .unwrap_or(UTF_8);
+        let body = response.bytes().await?;
+        self.record(&body);
+        Ok(encoding.decode(&body).0.into_owned())
+    }
+
+    pub fn responses(&self) -> usize {
+        self.responses.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Describes the totals and throughput, given the duration of the scan.
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let (responses, bytes) = (self.responses(), self.bytes());
+        let average = bytes.checked_div(responses as u64).unwrap_or_default();
+        let rate = bytes as f64 / 1e6 / elapsed.as_secs_f64();
+        format!(
+            "Downloaded {bytes} bytes in {responses} responses ({average} bytes/response, \
+             {rate:.2} MB/s)."
+        )
+    }
+}
+
+/// The charset parameter of a `Content-Type` header value.
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
@@ -39,91 +418,634 @@ struct ErrorResponse {
     error: Box<str>,
 }
 
+/// Options affecting how tickets are fetched and searched.
+#[derive(Clone, Debug)]
+pub struct ScanConfig {
+    /// The pattern of the flag. Its first capture group, or the whole match if it has none, is
+    /// taken as the flag.
+    pub flag_pattern: Regex,
+    /// If given, only matches of the flag pattern whose whole text, e.g. `flag{...}`, also matches
+    /// this are taken as the flag.
+    pub validate: Option<Regex>,
+    /// If given, base64 attachments of up to this many bytes are searched as well.
+    pub max_attachment_bytes: Option<usize>,
+    pub success: SuccessCheck,
+    /// Prints information about progress.
+    pub verbose: bool,
+    /// Reports requests as events instead of printing them.
+    pub events: Option<EventFormat>,
+    /// The path of the comments endpoint of each ticket relative to the index page, with `{id}`
+    /// in place of the ticket id. The comments are fetched and searched along with each ticket if
+    /// given.
+    pub comments_path: Option<Box<str>>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            flag_pattern: regex_flag().clone(),
+            validate: None,
+            max_attachment_bytes: None,
+            success: SuccessCheck::default(),
+            verbose: false,
+            events: None,
+            comments_path: None,
+        }
+    }
+}
+
+/// Requirements for a response to count as a ticket, besides deserializing as one.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SuccessCheck {
+    /// Requires a 2xx status.
+    pub status: bool,
+    /// Requires a top-level field to have the given value.
+    pub field: Option<(Box<str>, Box<str>)>,
+}
+
+impl SuccessCheck {
+    fn is_success(&self, status: StatusCode, body: &str) -> bool {
+        if self.status && !status.is_success() {
+            return false;
+        }
+        let Some((name, expected)) = &self.field else {
+            return true;
+        };
+
+        let Ok(Value::Object(fields)) = json_from_str(body) else {
+            return false;
+        };
+        match fields.get(&**name) {
+            Some(Value::String(value)) => value == &**expected,
+            // Non-string values, e.g. `true`, are compared as JSON.
+            Some(value) => json_from_str(expected).is_ok_and(|e: Value| &e == value),
+            None => false,
+        }
+    }
+}
+
+/// Searches the page that logging in leads to for the flag.
+pub fn search_landing_page(body: &str, config: &ScanConfig) -> Option<FlagMatch> {
+    FlagMatch::find(
+        &config.flag_pattern,
+        config.validate.as_ref(),
+        Field::LandingPage,
+        body,
+    )
+}
+
+/// The URL of ticket `id`, relative to the index page so that a server hosted under a path prefix
+/// can be scanned.
+pub fn ticket_url(index_url: &Url, id: usize) -> Url {
+    index_url.join(&format!("api/tickets/{id}")).unwrap()
+}
+
+/// The URL of the comments of ticket `id`, where `path` is relative to the index page and contains
+/// `{id}` in place of the id.
+pub fn comments_url(index_url: &Url, path: &str, id: usize) -> Url {
+    index_url
+        .join(&path.replace("{id}", &id.to_string()))
+        .expect("Invalid comments path.")
+}
+
+/// Finds the highest ticket id by probing exponentially growing ids and then bisecting, assuming
+/// that there are no gaps. Returns 0 if there are no tickets at all.
+pub async fn find_last_id(
+    client: &Client,
+    index_url: &Url,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<usize, ScanError> {
+    let exists = async |id| {
+        let ticket = fetch_ticket(client, index_url, id, config, stats).await?;
+        Ok::<_, ScanError>(ticket.is_some())
+    };
+
+    // Invariant: `low` exists (or is 0) and `high` doesn't.
+    let mut low = 0;
+    let mut high = 1;
+    while exists(high).await? {
+        if high == usize::MAX {
+            return Ok(high);
+        }
+        low = high;
+        high = high.saturating_mul(2);
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if exists(mid).await? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Fetches and deserializes a single ticket. Returns `None` if the ticket does not exist.
+pub async fn fetch_ticket(
+    client: &Client,
+    index_url: &Url,
+    id: usize,
+    config: &ScanConfig,
+    stats: &Stats,
+) -> Result<Option<Ticket>, ScanError> {
+    let ticket_url = ticket_url(index_url, id);
+    stats.start_request().await?;
+    let (status, body) = async {
+        let response = client.get(ticket_url).send().await?;
+        Ok::<_, ReqwestError>((response.status(), stats.text(response).await?))
+    }
+    .instrument(info_span!("fetch"))
+    .await?;
+
+    // The body is classified in this order:
+    // 1. Any body with an `error` field is an error, whatever the status and other fields, since
+    //    some servers report errors with 200 and a body that may otherwise pass as a ticket.
+    // 2. A body that is a ticket and passes the success check is a ticket.
+    // 3. Anything else is unknown, unless the status blames the server.
+    let (error, ticket) = info_span!("parse").in_scope(|| match json_from_str(&body) {
+        Ok(ErrorResponse { error }) => (Some(error), None),
+        Err(_) => (None, json_from_str::<Ticket>(&body).ok()),
+    });
+    if let Some(error) = error {
+        match &*error {
+            "Ticket not found" => Ok(None),
+            _ => Err(ScanError::Response(error)),
+        }
+    } else if let Some(ticket) = ticket
+        && config.success.is_success(status, &body)
+    {
+        let comments = match &config.comments_path {
+            Some(path) => fetch_comments(client, index_url, path, id, stats).await?,
+            None => None,
+        };
+        Ok(Some(Ticket {
+            status,
+            comments,
+            ..ticket
+        }))
+    } else if status.is_server_error() {
+        Err(ScanError::Status(status))
+    } else {
+        Err(ScanError::UnknownSchema(body.into()))
+    }
+}
+
+/// Fetches the raw comments of ticket `id` from `path`. Returns `None` if the ticket has no
+/// comments endpoint.
+async fn fetch_comments(
+    client: &Client,
+    index_url: &Url,
+    path: &str,
+    id: usize,
+    stats: &Stats,
+) -> Result<Option<Box<str>>, ScanError> {
+    let url = comments_url(index_url, path, id);
+    stats.start_request().await?;
+    let response = client.get(url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let body = stats.text(response).await?;
+    Ok(Some(body.into()))
+}
+
 pub async fn fetch_tickets(
     tx: Sender<Result<Ticket, ScanError>>,
     client: Arc<Client>,
     index_url: Arc<Url>,
-    counter: Arc<AtomicUsize>,
-    verbose: bool,
+    ids: Arc<Ids>,
+    stats: Arc<Stats>,
+    breaker: Option<Arc<Breaker>>,
+    config: Arc<ScanConfig>,
 ) {
-    loop {
-        let id = counter.fetch_add(1, Ordering::SeqCst);
-        if verbose {
+    while let Some(id) = ids.next() {
+        if let Some(events) = config.events {
+            events.emit(&Event::Request { id });
+        } else if config.verbose {
             println!("Fetching ticket {id}...");
         }
 
-        let ticket_url = index_url.join(&format!("/api/tickets/{id}")).unwrap();
+        let fetch = || fetch_ticket(&client, &index_url, id, &config, &stats);
+        let result = match &breaker {
+            Some(breaker) => {
+                // Error responses and unexpected bodies are still answers, which would only be the
+                // same if requested again, unlike failed requests or server errors.
+                let healthy =
+                    |r: &Result<_, _>| !matches!(r, Err(ScanError::Io(_) | ScanError::Status(_)));
+                breaker.call(fetch, healthy).await
+            }
+            None => fetch().await,
+        };
+
+        let ticket = match result {
+            Ok(Some(ticket)) => {
+                ids.found(id);
+                Ok(ticket)
+            }
+            // No more tickets: will be handled in `main`.
+            Ok(None) if ids.is_end(id) => break,
+            Ok(None) => continue,
+            Err(ScanError::LimitReached) => break,
+            Err(ScanError::Response(error)) if &*error == "Not authenticated" => {
+                panic!("Invalid session.")
+            }
+            Err(e) => Err(e),
+        };
+        check_capacity(config.verbose, &tx);
+        // No explicit yielding is needed to keep the processor fed: it runs on the main thread
+        // rather than competing for the runtime's workers, and `send` consumes the task's
+        // cooperative budget, so even a very fast server can't keep a worker from yielding.
+        if tx.send(ticket).await.is_err() {
+            // Receiver has closed: flag is found.
+            break;
+        }
+    }
+}
 
-        async fn fetch(client: &Client, url: Url) -> Result<Bytes, ReqwestError> {
-            client.get(url).send().await?.bytes().await
+/// Like [`fetch_tickets`], but treats each response body as plain text instead of deserializing it.
+/// 404 responses are taken to mean the ticket does not exist.
+pub async fn fetch_raw_tickets(
+    tx: Sender<Result<Ticket, ScanError>>,
+    client: Arc<Client>,
+    index_url: Arc<Url>,
+    ids: Arc<Ids>,
+    stats: Arc<Stats>,
+    breaker: Option<Arc<Breaker>>,
+    config: Arc<ScanConfig>,
+) {
+    while let Some(id) = ids.next() {
+        if let Some(events) = config.events {
+            events.emit(&Event::Request { id });
+        } else if config.verbose {
+            println!("Fetching ticket {id}...");
         }
 
-        fn check_capacity(verbose: bool, tx: &Sender<Result<Ticket, ScanError>>) {
-            if verbose {
-                let capacity = tx.capacity();
-                if capacity <= BUFFER_CAPACITY_WARNING {
-                    eprintln!("Buffer nearly full ({capacity} left).");
+        let ticket_url = ticket_url(&index_url, id);
+
+        async fn fetch(
+            client: &Client,
+            url: Url,
+            stats: &Stats,
+        ) -> Result<Option<(StatusCode, String)>, ScanError> {
+            stats.start_request().await?;
+            async {
+                let response = client.get(url).send().await?;
+                let status = response.status();
+                if status == StatusCode::NOT_FOUND {
+                    return Ok(None);
                 }
+                Ok(Some((status, stats.text(response).await?)))
+            }
+            .instrument(info_span!("fetch"))
+            .await
+        }
+
+        let result = match &breaker {
+            Some(breaker) => {
+                let healthy = |r: &Result<Option<(StatusCode, _)>, _>| match r {
+                    Ok(Some((status, _))) => !status.is_server_error(),
+                    Ok(None) | Err(ScanError::LimitReached) => true,
+                    Err(_) => false,
+                };
+                breaker
+                    .call(|| fetch(&client, ticket_url.clone(), &stats), healthy)
+                    .await
+            }
+            None => fetch(&client, ticket_url, &stats).await,
+        };
+
+        let ticket = match result {
+            // The whole body is searched as if it were the description.
+            Ok(Some((status, body))) => {
+                ids.found(id);
+                Ok(Ticket {
+                    id,
+                    subject: "".into(),
+                    description: body.into(),
+                    status,
+                    created_at: None,
+                    comments: None,
+                })
             }
+            // No more tickets: will be handled in `main`.
+            Ok(None) if ids.is_end(id) => break,
+            Ok(None) => continue,
+            Err(ScanError::LimitReached) => break,
+            Err(e) => Err(e),
+        };
+        check_capacity(config.verbose, &tx);
+        if tx.send(ticket).await.is_err() {
+            // Receiver has closed: flag is found.
+            break;
         }
+    }
+}
 
-        // If receiver has closed, these errors are not relevant anymore since the flag is found.
-        match fetch(&client, ticket_url).await {
-            Ok(bytes) => {
-                if let Ok(ticket) = json_from_slice(&bytes) {
-                    check_capacity(verbose, &tx);
-                    if tx.send(Ok(ticket)).await.is_err() {
-                        // Receiver has closed: flag is found.
-                        break;
+/// Fetches tickets without decoding, parsing or searching them, to measure the throughput of the
+/// server alone. 404 responses are taken to mean the ticket does not exist.
+pub async fn fetch_bytes(
+    tx: Sender<Result<Ticket, ScanError>>,
+    client: Arc<Client>,
+    index_url: Arc<Url>,
+    ids: Arc<Ids>,
+    stats: Arc<Stats>,
+    config: Arc<ScanConfig>,
+) {
+    while let Some(id) = ids.next() {
+        if let Some(events) = config.events {
+            events.emit(&Event::Request { id });
+        } else if config.verbose {
+            println!("Fetching ticket {id}...");
+        }
+
+        let fetch = async {
+            stats.start_request().await?;
+            let response = client.get(ticket_url(&index_url, id)).send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(false);
+            }
+            stats.record(response.bytes().await?);
+            Ok(true)
+        };
+
+        match fetch.await {
+            Ok(true) => ids.found(id),
+            // No more tickets.
+            Ok(false) if ids.is_end(id) => break,
+            Ok(false) => {}
+            Err(ScanError::LimitReached) => break,
+            Err(e) => {
+                if tx.send(Err(e)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn check_capacity(verbose: bool, tx: &Sender<Result<Ticket, ScanError>>) {
+    if verbose {
+        let capacity = tx.capacity();
+        if capacity <= BUFFER_CAPACITY_WARNING {
+            eprintln!("Buffer nearly full ({capacity} left).");
+        }
+    }
+}
+
+/// How a list endpoint links to the next page.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pagination {
+    /// `Link: <...>; rel="next"` headers (RFC 5988), with each page being an array of tickets.
+    Link,
+    /// An opaque cursor in each page, passed back as the `cursor` query parameter. Both fields are
+    /// JSON pointers into the page.
+    Cursor { items: Box<str>, cursor: Box<str> },
+}
+
+/// Fetches pages of tickets from a list endpoint until no next page is advertised.
+pub async fn fetch_pages(
+    tx: Sender<Result<Ticket, ScanError>>,
+    client: Arc<Client>,
+    mut page_url: Url,
+    pagination: Pagination,
+    stats: Arc<Stats>,
+    config: Arc<ScanConfig>,
+) {
+    loop {
+        if let Some(events) = config.events {
+            events.emit(&Event::Page {
+                url: page_url.as_str(),
+            });
+        } else if config.verbose {
+            println!("Fetching page {page_url}...");
+        }
+
+        async fn fetch(
+            client: &Client,
+            url: Url,
+            stats: &Stats,
+        ) -> Result<(Option<Url>, StatusCode, String), ScanError> {
+            stats.start_request().await?;
+            let response = client.get(url.clone()).send().await?;
+            let status = response.status();
+            let next = response
+                .headers()
+                .get_all(LINK)
+                .iter()
+                .filter_map(|h| h.to_str().ok())
+                .find_map(next_link)
+                .and_then(|link| url.join(link).ok());
+            Ok((next, status, stats.text(response).await?))
+        }
+
+        let next = match fetch(&client, page_url.clone(), &stats).await {
+            Ok((next, status, body)) => {
+                let page = match &pagination {
+                    Pagination::Link => json_from_str(&body).ok().map(|tickets| (tickets, next)),
+                    Pagination::Cursor { items, cursor } => {
+                        cursor_page(&body, items, cursor, &page_url)
                     }
-                } else if let Ok(ErrorResponse { error }) = json_from_slice(&bytes) {
-                    match &*error {
-                        // No more tickets: will be handled in `main`.
-                        "Ticket not found" => break,
-                        "Not authenticated" => panic!("Invalid session."),
-                        _ => {
-                            check_capacity(verbose, &tx);
-                            _ = tx.send(Err(ScanError::Response(error)));
+                };
+
+                if let Some((tickets, next)) = page {
+                    for ticket in tickets {
+                        let ticket = Ticket { status, ..ticket };
+                        check_capacity(config.verbose, &tx);
+                        if tx.send(Ok(ticket)).await.is_err() {
+                            // Receiver has closed: flag is found.
+                            return;
                         }
                     }
+                    next
+                } else if let Ok(ErrorResponse { error }) = json_from_str(&body) {
+                    check_capacity(config.verbose, &tx);
+                    _ = tx.send(Err(ScanError::Response(error))).await;
+                    None
                 } else {
-                    check_capacity(verbose, &tx);
-                    _ = tx.send(Err(ScanError::UnknownSchema(bytes)));
+                    check_capacity(config.verbose, &tx);
+                    _ = tx.send(Err(ScanError::UnknownSchema(body.into()))).await;
+                    None
                 }
             }
+            Err(ScanError::LimitReached) => None,
             Err(e) => {
-                check_capacity(verbose, &tx);
-                _ = tx.send(Err(e.into()))
+                check_capacity(config.verbose, &tx);
+                _ = tx.send(Err(e)).await;
+                None
             }
+        };
+
+        match next {
+            Some(url) => page_url = url,
+            None => break,
         }
     }
 }
 
-fn regex_flag(haystack: &str) -> Option<&str> {
+/// Reads the tickets of a cursor-paginated page, along with the URL of the next page if the cursor
+/// is present.
+fn cursor_page(
+    body: &str,
+    items: &str,
+    cursor: &str,
+    url: &Url,
+) -> Option<(Vec<Ticket>, Option<Url>)> {
+    let page: Value = json_from_str(body).ok()?;
+    let tickets = Vec::deserialize(page.pointer(items)?).ok()?;
+
+    let cursor = match page.pointer(cursor) {
+        Some(Value::String(s)) if !s.is_empty() => Some(s.clone()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    };
+    let next = cursor.map(|cursor| {
+        let pairs: Vec<_> = url
+            .query_pairs()
+            .filter(|(k, _)| k != "cursor")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        let mut next = url.clone();
+        next.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair("cursor", &cursor);
+        next
+    });
+
+    Some((tickets, next))
+}
+
+/// Fetches every ticket at once from an endpoint serving them as a single array. Returns `None` if
+/// the endpoint is unavailable or serves something else.
+pub async fn fetch_all(
+    client: &Client,
+    url: Url,
+    stats: &Stats,
+) -> Result<Option<Vec<Ticket>>, ScanError> {
+    stats.start_request().await?;
+    let response = client.get(url).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Ok(None);
+    }
+    let body = stats.text(response).await?;
+
+    Ok(json_from_str::<Vec<Ticket>>(&body).ok().map(|tickets| {
+        tickets
+            .into_iter()
+            .map(|ticket| Ticket { status, ..ticket })
+            .collect()
+    }))
+}
+
+/// Sends already fetched tickets, as if they were being fetched.
+pub async fn send_tickets(tx: Sender<Result<Ticket, ScanError>>, tickets: Vec<Ticket>) {
+    for ticket in tickets {
+        if tx.send(Ok(ticket)).await.is_err() {
+            // Receiver has closed: flag is found.
+            return;
+        }
+    }
+}
+
+/// Extracts the target of the `rel="next"` link from a `Link` header value, if present.
+fn next_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let (target, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+        params
+            .split(';')
+            .filter_map(|p| p.trim().strip_prefix("rel="))
+            .any(|rel| {
+                rel.trim_matches('"')
+                    .split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("next"))
+            })
+            .then_some(target)
+    })
+}
+
+fn regex_flag() -> &'static Regex {
     static R: OnceLock<Regex> = OnceLock::new();
     // We don't know the exact format of the flag contents, but we assume it at least doesn't contain
     // any '}' characters.
     R.get_or_init(|| Regex::new(r"flag\{(.*?)\}").unwrap())
-        .captures(haystack)
-        .map(|c| c.get(1).unwrap().as_str())
 }
 
+/// Matches a brace-delimited, flag-like pattern, capturing its prefix.
+fn regex_flag_like() -> &'static Regex {
+    static R: OnceLock<Regex> = OnceLock::new();
+    R.get_or_init(|| Regex::new(r"(\w+)\{[^}]*\}").unwrap())
+}
+
+/// Counts the tickets containing a flag-like pattern (`prefix{...}`) in each field.
+pub fn field_matches(tickets: &[Ticket]) -> [(Field, usize); 2] {
+    let pattern = regex_flag_like();
+    let count = |field: fn(&Ticket) -> &str| {
+        tickets
+            .iter()
+            .filter(|t| pattern.is_match(field(t)))
+            .count()
+    };
+    [
+        (Field::Subject, count(|t| &t.subject)),
+        (Field::Description, count(|t| &t.description)),
+    ]
+}
+
+/// Counts how often each prefix of a brace-delimited, flag-like pattern (`prefix{...}`) occurs in
+/// the tickets, most common first.
+pub fn flag_prefixes(tickets: &[Ticket]) -> Vec<(Box<str>, usize)> {
+    let pattern = regex_flag_like();
+    let mut counts = HashMap::<_, usize>::new();
+    for ticket in tickets {
+        for field in [&ticket.subject, &ticket.description] {
+            for c in pattern.captures_iter(field) {
+                *counts.entry(c.get(1).unwrap().as_str()).or_default() += 1;
+            }
+        }
+    }
+
+    let mut counts: Vec<(Box<str>, _)> = counts.into_iter().map(|(p, n)| (p.into(), n)).collect();
+    counts.sort_unstable_by(|(p1, n1), (p2, n2)| n2.cmp(n1).then_with(|| p1.cmp(p2)));
+    counts
+}
+
+/// Feeds every ticket to `processor` until it finds the flag or asks to stop.
 pub async fn process_tickets(
     mut rx: Receiver<Result<Ticket, ScanError>>,
+    processor: &mut impl TicketProcessor,
 ) -> Result<Scan, ScanError> {
+    let mut skipped = 0;
     while let Some(ticket) = rx.recv().await {
-        let Ticket {
-            id,
-            subject,
-            description,
-        } = ticket?;
-        if let Some(flag) = regex_flag(&subject).or_else(|| regex_flag(&description)) {
-            return Ok(Scan::Success {
-                flag: flag.into(),
-                id,
-            });
+        let ticket = match ticket {
+            Ok(ticket) => ticket,
+            // A failed request or server error says nothing about the other tickets, so one of
+            // the many in flight shouldn't end the scan. Anything else would recur for every
+            // ticket, e.g. due to a wrong `--success-field`.
+            Err(e @ (ScanError::Io(_) | ScanError::Status(_))) => {
+                eprintln!("Skipping a ticket that failed to be fetched: {e}");
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        match processor.process(&ticket) {
+            ProcessOutcome::FlagFound(found) => {
+                return Ok(Scan::Success {
+                    id: ticket.id,
+                    status: ticket.status,
+                    found,
+                });
+            }
+            ProcessOutcome::Continue => {}
+            ProcessOutcome::Stop => break,
         }
     }
 
+    if skipped > 0 {
+        eprintln!("{skipped} tickets were skipped after failing to be fetched.");
+    }
     Ok(Scan::Failure)
 }
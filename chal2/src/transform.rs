@@ -0,0 +1,52 @@
+use base64::{DecodeError, Engine, prelude::BASE64_STANDARD};
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use percent_encoding::percent_decode_str;
+use std::string::FromUtf8Error;
+use thiserror::Error;
+
+/// A transformation applied to a found flag before it is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum Transform {
+    Trim,
+    Lowercase,
+    Base64,
+    UrlDecode,
+    Rot13,
+}
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+    #[error("{0}")]
+    Base64(#[from] DecodeError),
+    #[error("{0}")]
+    Utf8(#[from] FromUtf8Error),
+}
+
+impl Transform {
+    pub fn apply(self, s: &str) -> Result<String, TransformError> {
+        Ok(match self {
+            Self::Trim => s.trim().into(),
+            Self::Lowercase => s.to_lowercase(),
+            Self::Base64 => String::from_utf8(BASE64_STANDARD.decode(s.trim())?)?,
+            Self::UrlDecode => String::from_utf8(percent_decode_str(s).collect())?,
+            Self::Rot13 => s.chars().map(rot13).collect(),
+        })
+    }
+}
+
+/// Applies each transformation in order.
+pub fn apply_all(transforms: &[Transform], s: &str) -> Result<String, TransformError> {
+    transforms
+        .iter()
+        .try_fold(s.to_owned(), |s, transform| transform.apply(&s))
+}
+
+fn rot13(c: char) -> char {
+    match c {
+        'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+        'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+        _ => c,
+    }
+}